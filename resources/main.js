@@ -1,6 +1,241 @@
-function say_hello() {
-    console.log("hello there");
+// tells the stylesheet javascript is running, so placeholders are shown
+document.documentElement.classList.add("js");
+
+// swaps a placeholder for the thumbnail named in its data-src
+function load_image(img) {
+    img.addEventListener("load", function () {
+        img.classList.add("loaded");
+    }, { once: true });
+    img.src = img.dataset.src;
+    img.removeAttribute("data-src");
+}
+
+// loads thumbnails as they come near the viewport, or all at once in browsers
+// without IntersectionObserver
+function lazy_load() {
+    var images = document.querySelectorAll("img[data-src]");
+    if (!("IntersectionObserver" in window)) {
+        images.forEach(load_image);
+        return;
+    }
+    var observer = new IntersectionObserver(function (entries) {
+        entries.forEach(function (entry) {
+            if (entry.isIntersecting) {
+                observer.unobserve(entry.target);
+                load_image(entry.target);
+            }
+        });
+    }, { rootMargin: "400px 0px" });
+    images.forEach(function (img) {
+        observer.observe(img);
+    });
+}
+
+var TILE_SIZE = 256;
+var MAX_ZOOM = 18;
+
+// web mercator pixel position of a point at a zoom level
+function project(lat, lon, zoom) {
+    var scale = TILE_SIZE * Math.pow(2, zoom);
+    var sin = Math.sin(Math.max(-85, Math.min(85, lat)) * Math.PI / 180);
+    return {
+        x: (lon + 180) / 360 * scale,
+        y: (0.5 - Math.log((1 + sin) / (1 - sin)) / (4 * Math.PI)) * scale
+    };
+}
+
+// the closest zoom that still fits every point in the map
+function fit_zoom(points, width, height) {
+    for (var zoom = MAX_ZOOM; zoom > 0; zoom--) {
+        var xs = points.map(function (point) { return project(point.lat, point.lon, zoom).x; });
+        var ys = points.map(function (point) { return project(point.lat, point.lon, zoom).y; });
+        if (Math.max.apply(null, xs) - Math.min.apply(null, xs) < width - 60
+            && Math.max.apply(null, ys) - Math.min.apply(null, ys) < height - 60) {
+            return Math.min(zoom, 15);
+        }
+    }
+    return 0;
+}
+
+// draws the tiles and markers of a map around its centre, a pixel position at
+// its zoom level
+function draw_map(map) {
+    var layer = map.layer;
+    var width = layer.clientWidth;
+    var height = layer.clientHeight;
+    var left = map.centre.x - width / 2;
+    var top = map.centre.y - height / 2;
+    var tile_count = Math.pow(2, map.zoom);
+    layer.textContent = "";
+    for (var tile_x = Math.floor(left / TILE_SIZE); tile_x * TILE_SIZE < left + width; tile_x++) {
+        for (var tile_y = Math.max(0, Math.floor(top / TILE_SIZE)); tile_y * TILE_SIZE < top + height && tile_y < tile_count; tile_y++) {
+            var tile = document.createElement("img");
+            tile.className = "map_tile";
+            tile.alt = "";
+            tile.style.left = (tile_x * TILE_SIZE - left) + "px";
+            tile.style.top = (tile_y * TILE_SIZE - top) + "px";
+            // without tiles, go back to the static map
+            tile.addEventListener("error", map.fail, { once: true });
+            tile.src = map.tiles
+                .replace("{z}", map.zoom)
+                .replace("{x}", ((tile_x % tile_count) + tile_count) % tile_count)
+                .replace("{y}", tile_y);
+            layer.appendChild(tile);
+        }
+    }
+    map.points.forEach(function (point) {
+        var position = project(point.lat, point.lon, map.zoom);
+        var marker = document.createElement("a");
+        marker.className = "map_marker";
+        marker.href = point.href;
+        marker.title = point.label;
+        marker.style.left = (position.x - left) + "px";
+        marker.style.top = (position.y - top) + "px";
+        if (point.thumbnail) {
+            var thumbnail = document.createElement("img");
+            thumbnail.src = point.thumbnail;
+            thumbnail.alt = point.label;
+            marker.appendChild(thumbnail);
+        }
+        layer.appendChild(marker);
+    });
+}
+
+// swaps a map's static svg for one drawn over tiles, which can be dragged and
+// zoomed. The svg stays if there is no tile server or its tiles don't load
+function init_map(element) {
+    var tiles = element.dataset.tiles;
+    var points = JSON.parse(element.dataset.points || "[]");
+    if (!tiles || points.length === 0) {
+        return;
+    }
+    var svg = element.querySelector(".map_svg");
+    var layer = document.createElement("div");
+    layer.className = "map_layer";
+    element.appendChild(layer);
+    var map = { layer: layer, tiles: tiles, points: points };
+    map.fail = function () {
+        layer.remove();
+        if (svg) { svg.style.display = ""; }
+    };
+    map.zoom = fit_zoom(points, layer.clientWidth, layer.clientHeight);
+    var xs = points.map(function (point) { return project(point.lat, point.lon, map.zoom).x; });
+    var ys = points.map(function (point) { return project(point.lat, point.lon, map.zoom).y; });
+    map.centre = {
+        x: (Math.min.apply(null, xs) + Math.max.apply(null, xs)) / 2,
+        y: (Math.min.apply(null, ys) + Math.max.apply(null, ys)) / 2
+    };
+    if (svg) { svg.style.display = "none"; }
+    draw_map(map);
+
+    function zoom_by(change) {
+        var zoom = Math.max(0, Math.min(MAX_ZOOM, map.zoom + change));
+        var factor = Math.pow(2, zoom - map.zoom);
+        map.centre = { x: map.centre.x * factor, y: map.centre.y * factor };
+        map.zoom = zoom;
+        draw_map(map);
+    }
+    ["+", "-"].forEach(function (label) {
+        var button = document.createElement("button");
+        button.className = "map_zoom";
+        button.textContent = label;
+        button.addEventListener("click", function () { zoom_by(label === "+" ? 1 : -1); });
+        element.appendChild(button);
+    });
+
+    var drag = null;
+    layer.addEventListener("pointerdown", function (event) {
+        if (event.target.closest(".map_marker")) {
+            return;
+        }
+        drag = { x: event.clientX, y: event.clientY };
+        layer.setPointerCapture(event.pointerId);
+    });
+    layer.addEventListener("pointermove", function (event) {
+        if (drag) {
+            map.centre = { x: map.centre.x - (event.clientX - drag.x), y: map.centre.y - (event.clientY - drag.y) };
+            drag = { x: event.clientX, y: event.clientY };
+            draw_map(map);
+        }
+    });
+    layer.addEventListener("pointerup", function () { drag = null; });
+}
+
+var MAX_SEARCH_RESULTS = 60;
+
+// every word of the query has to appear somewhere in what is searched
+function matches_query(text, words) {
+    return words.every(function (word) { return text.indexOf(word) !== -1; });
+}
+
+// a search box at the top of the page, filtering the index built alongside the
+// album as you type. Links are made relative to the page through `root`
+function init_search() {
+    var index = window.ALBUM_SEARCH_INDEX;
+    var script = document.querySelector("script[data-root]");
+    if (!index || !script) {
+        return;
+    }
+    var root = script.dataset.root + "/";
+    var albums = index.albums.map(function (album) {
+        return { path: album, text: (album || "photo album").toLowerCase() };
+    });
+    var photos = index.photos.map(function (photo) {
+        return {
+            name: photo[0], href: photo[2], thumbnail: photo[3], caption: photo[4],
+            text: [photo[0], index.albums[photo[1]], photo[4], photo[5].join(" "), photo[6]].join(" ").toLowerCase()
+        };
+    });
+
+    var box = document.createElement("div");
+    box.className = "search_box";
+    var input = document.createElement("input");
+    input.type = "search";
+    input.placeholder = "search names, captions, tags and dates";
+    var results = document.createElement("div");
+    results.className = "search_results";
+    box.appendChild(input);
+    box.appendChild(results);
+    document.body.insertBefore(box, document.body.firstChild);
+
+    input.addEventListener("input", function () {
+        var words = input.value.toLowerCase().split(/\s+/).filter(function (word) { return word; });
+        results.textContent = "";
+        if (words.length === 0) {
+            return;
+        }
+        albums.filter(function (album) { return matches_query(album.text, words); })
+            .forEach(function (album) {
+                var link = document.createElement("a");
+                link.className = "search_album";
+                link.href = root + (album.path ? album.path + "/" : "") + "index.html";
+                link.textContent = album.path || "Photo Album";
+                results.appendChild(link);
+            });
+        var found = photos.filter(function (photo) { return matches_query(photo.text, words); });
+        found.slice(0, MAX_SEARCH_RESULTS).forEach(function (photo) {
+            var link = document.createElement("a");
+            link.className = "search_result";
+            link.href = root + photo.href;
+            link.title = photo.caption || photo.name;
+            var thumbnail = document.createElement("img");
+            thumbnail.src = root + photo.thumbnail;
+            thumbnail.alt = photo.name;
+            thumbnail.loading = "lazy";
+            link.appendChild(thumbnail);
+            results.appendChild(link);
+        });
+        var summary = document.createElement("p");
+        summary.className = "search_summary";
+        summary.textContent = found.length > MAX_SEARCH_RESULTS
+            ? "showing " + MAX_SEARCH_RESULTS + " of " + found.length + " photos"
+            : found.length + (found.length === 1 ? " photo" : " photos");
+        results.appendChild(summary);
+    });
 }
 
-document.addEventListener("load", say_hello);
-console.log("also hello")
\ No newline at end of file
+document.addEventListener("DOMContentLoaded", function () {
+    init_search();
+    lazy_load();
+    document.querySelectorAll(".photo_map").forEach(init_map);
+});
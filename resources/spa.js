@@ -0,0 +1,170 @@
+// the single page app built by --output spa. Albums are drawn from the
+// album.json manifest in each album's cacheDir, chosen by the #/path in the url.
+// Manifests are fetched, so the tree has to be served over http
+
+var MANIFEST_FILE_NAME = "album.json";
+
+// the album path in the url, "" for the top album
+function current_path() {
+    return decodeURIComponent(location.hash.replace(/^#\/?/, "")).replace(/\/+$/, "");
+}
+
+// a path from a manifest as a url relative to the shell
+function path_url(path) {
+    return path.split("/").map(encodeURIComponent).join("/");
+}
+
+function manifest_url(path) {
+    return (path ? path_url(path) + "/" : "") + "cacheDir/" + MANIFEST_FILE_NAME;
+}
+
+function album_link(path) {
+    return "#/" + path_url(path);
+}
+
+function element(tag, class_name, text) {
+    var el = document.createElement(tag);
+    if (class_name) {
+        el.className = class_name;
+    }
+    if (text !== undefined) {
+        el.textContent = text;
+    }
+    return el;
+}
+
+function star_string(rating) {
+    var stars = Math.max(0, Math.min(5, rating));
+    return "★".repeat(stars) + "☆".repeat(5 - stars);
+}
+
+function label_class(label) {
+    return "label_" + label.toLowerCase().replace(/[^a-z0-9]/g, "_");
+}
+
+// links back up to the top album, one per directory
+function format_breadcrumbs(manifest) {
+    var nav = element("div", "detail_nav");
+    var parts = manifest.path ? manifest.path.split("/") : [];
+    var top = element("a", "page_link", "Photo Album");
+    top.href = album_link("");
+    nav.appendChild(top);
+    parts.forEach(function (part, index) {
+        nav.appendChild(document.createTextNode(" / "));
+        var link = element("a", "page_link", part);
+        link.href = album_link(parts.slice(0, index + 1).join("/"));
+        nav.appendChild(link);
+    });
+    return nav;
+}
+
+function format_album(album) {
+    var item = element("div", "dirs_item");
+    var link = element("a", "dirs_link");
+    link.href = album_link(album.path);
+    if (album.cover) {
+        var cover = element("img", "dirs_img");
+        cover.src = path_url(album.cover);
+        cover.alt = album.name;
+        cover.loading = "lazy";
+        link.appendChild(cover);
+    }
+    link.appendChild(element("p", null, album.name));
+    item.appendChild(link);
+    return item;
+}
+
+// a photo's paths are relative to its album, `album_path`
+function format_photo(photo, album_path) {
+    var in_album = function (path) {
+        return path_url((album_path ? album_path + "/" : "") + path.replace(/\\/g, "/"));
+    };
+    var tags = photo.tags;
+    var item = element("div", "images_item");
+    if (tags.label) {
+        item.classList.add(label_class(tags.label));
+    }
+    var link = element("a", "images_link");
+    link.href = in_album(photo.web_rendition || photo.file);
+    var img = element("img", "disp_img");
+    img.src = in_album(photo.thumbnail);
+    img.alt = photo.caption || photo.file.split(/[\\/]/).pop();
+    img.loading = "lazy";
+    img.decoding = "async";
+    if (photo.thumbnail_size) {
+        img.width = photo.thumbnail_size[0];
+        img.height = photo.thumbnail_size[1];
+    }
+    link.appendChild(img);
+    item.appendChild(link);
+    if (tags.title) {
+        item.appendChild(element("p", "image_title", tags.title));
+    }
+    if (tags.rating > 0) {
+        item.appendChild(element("p", "image_rating", star_string(tags.rating)));
+    }
+    if (photo.caption) {
+        item.appendChild(element("p", "image_caption", photo.caption));
+    }
+    return item;
+}
+
+function draw_album(app, manifest) {
+    app.textContent = "";
+    document.title = manifest.name || "Photo Album";
+    app.appendChild(format_breadcrumbs(manifest));
+    // the description is html the album builder rendered from the README.md
+    if (manifest.description) {
+        var description = element("div", "album_description");
+        description.innerHTML = manifest.description;
+        app.appendChild(description);
+    }
+    if (manifest.albums.length > 0) {
+        app.appendChild(element("p", null, "Sub Directories"));
+        var albums = element("div", "dirs_list");
+        manifest.albums.forEach(function (album) {
+            albums.appendChild(format_album(album));
+        });
+        app.appendChild(albums);
+    }
+    if (manifest.photos.length > 0) {
+        app.appendChild(element("p", null, "Images"));
+        var photos = element("div", "images_list " + app.dataset.layout);
+        manifest.photos.forEach(function (photo) {
+            photos.appendChild(format_photo(photo, manifest.path));
+        });
+        app.appendChild(photos);
+    }
+}
+
+function show_album(app) {
+    var path = current_path();
+    fetch(manifest_url(path))
+        .then(function (response) {
+            if (!response.ok) {
+                throw new Error(response.status + " " + response.statusText);
+            }
+            return response.json();
+        })
+        .then(function (manifest) {
+            draw_album(app, manifest);
+            window.scrollTo(0, 0);
+        })
+        .catch(function (reason) {
+            app.textContent = "";
+            app.appendChild(element("p", "spa_error",
+                "could not load the album \"" + (path || "/") + "\": " + reason.message +
+                (location.protocol === "file:" ? ". Albums have to be served over http to be browsed" : "")));
+        });
+}
+
+document.addEventListener("DOMContentLoaded", function () {
+    var app = document.querySelector(".spa_album");
+    if (!app) {
+        return;
+    }
+    show_album(app);
+    window.addEventListener("hashchange", function () {
+        show_album(app);
+    });
+});
@@ -0,0 +1,83 @@
+use std::{
+    fs,
+    path::Path,
+};
+
+use log::warn;
+use serde::Deserialize;
+
+use super::thumbnail::{CropAnchor,ThumbnailMode,ThumbnailSettings};
+
+pub const ALBUM_CONFIG_FILE_NAME: &str = "album.toml";
+
+// settings from an album.toml, which apply to the folder it is in and every
+// folder below it, overriding the command line
+#[derive(Debug,Default,Deserialize)]
+#[serde(default)]
+pub struct AlbumConfig {
+    pub thumbnails: ThumbnailOverrides,
+    // keywords given to every photo, as if they had been tagged with them
+    pub tags:       Vec<String>,
+}
+
+#[derive(Debug,Default,Deserialize)]
+#[serde(default)]
+pub struct ThumbnailOverrides {
+    pub size:   Option<u32>,
+    pub mode:   Option<ThumbnailMode>,
+    pub anchor: Option<CropAnchor>,
+}
+
+impl AlbumConfig {
+    // reads the album.toml in `dir`, if there is one. A file that can't be
+    // understood is reported and otherwise ignored
+    pub fn load(dir: &Path) -> Option<Self> {
+        let path = dir.join(ALBUM_CONFIG_FILE_NAME);
+        let text = fs::read_to_string(&path).ok()?;
+        match toml::from_str(&text) {
+            Ok(config) => Some(config),
+            Err(reason) => {
+                warn!("{:?} - {}", path, reason);
+                None
+            },
+        }
+    }
+
+    pub fn apply(&self, settings: &ThumbnailSettings) -> ThumbnailSettings {
+        let overrides = &self.thumbnails;
+        ThumbnailSettings {
+            size:   overrides.size.filter(|size| *size > 0).unwrap_or(settings.size),
+            mode:   overrides.mode.unwrap_or(settings.mode),
+            anchor: overrides.anchor.unwrap_or(settings.anchor),
+            ..*settings
+        }
+    }
+
+    // the inherited tags with this directory's added
+    pub fn apply_tags(&self, tags: &[String]) -> Vec<String> {
+        let mut tags = tags.to_vec();
+        tags.extend(self.tags.iter().filter(|tag| !tag.trim().is_empty()).map(|tag| tag.trim().to_owned()));
+        tags
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn overrides_thumbnail_settings() {
+        let config : AlbumConfig = toml::from_str("[thumbnails]\nmode = \"square\"\nanchor = \"smart\"\n").unwrap();
+        let settings = config.apply(&ThumbnailSettings::default());
+        assert_eq!(settings.mode, ThumbnailMode::Square);
+        assert_eq!(settings.anchor, CropAnchor::Smart);
+        assert_eq!(settings.size, ThumbnailSettings::default().size);
+
+        assert!(toml::from_str::<AlbumConfig>("[thumbnails]\nmode = \"wide\"\n").is_err());
+        let empty : AlbumConfig = toml::from_str("").unwrap();
+        assert_eq!(empty.apply(&settings).mode, ThumbnailMode::Square);
+
+        let tagged : AlbumConfig = toml::from_str("tags = [\"holiday\", \" \"]\n").unwrap();
+        assert_eq!(tagged.apply_tags(&[String::from("2021")]), vec!["2021", "holiday"]);
+    }
+}
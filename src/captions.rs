@@ -0,0 +1,127 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+};
+
+use log::warn;
+use pulldown_cmark::{html,Options,Parser};
+
+pub const CAPTIONS_TOML_FILE_NAME: &str = "captions.toml";
+pub const CAPTIONS_CSV_FILE_NAME: &str = "captions.csv";
+pub const README_FILE_NAME: &str = "README.md";
+
+// the captions a directory gives its photos in a captions.toml, mapping file
+// names to captions, or a captions.csv of file name and caption rows. Where a
+// photo is in both, the toml wins
+#[derive(Debug,Default)]
+pub struct DirCaptions {
+    captions: HashMap<String, String>,
+}
+
+impl DirCaptions {
+    pub fn load(dir: &Path) -> Self {
+        let mut captions = HashMap::new();
+        captions.extend(read_captions_csv(&dir.join(CAPTIONS_CSV_FILE_NAME)));
+        captions.extend(read_captions_toml(&dir.join(CAPTIONS_TOML_FILE_NAME)));
+        Self { captions }
+    }
+
+    // the caption from a photo's text sidecar or this directory's caption
+    // files, in that order: photo.jpg.txt, captions.toml then captions.csv.
+    // Descriptions in xmp, iptc and exif are only used if none of these give one
+    pub fn caption_for(&self, path: &Path) -> Option<String> {
+        let file_name = path.file_name()?.to_string_lossy();
+        let text_sidecar = path.with_file_name(format!("{}.txt", file_name));
+        fs::read_to_string(text_sidecar).ok()
+            .map(|text| text.trim().to_owned())
+            .filter(|text| !text.is_empty())
+            .or_else(|| self.captions.get(file_name.as_ref()).cloned())
+    }
+}
+
+fn read_captions_toml(path: &Path) -> HashMap<String, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => return HashMap::new(),
+    };
+    match toml::from_str(&text) {
+        Ok(captions) => captions,
+        Err(reason) => {
+            warn!("{:?} - {}", path, reason);
+            HashMap::new()
+        },
+    }
+}
+
+// rows without a caption, and a header row naming the `file` column, are skipped
+fn read_captions_csv(path: &Path) -> HashMap<String, String> {
+    let mut reader = match csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .comment(Some(b'#'))
+        .from_path(path) {
+        Ok(reader) => reader,
+        Err(_) => return HashMap::new(),
+    };
+    let mut captions = HashMap::new();
+    for (index, record) in reader.records().enumerate() {
+        let record = match record {
+            Ok(record) => record,
+            Err(reason) => {
+                warn!("{:?} - {}", path, reason);
+                break;
+            },
+        };
+        match (record.get(0), record.get(1)) {
+            (Some(name), _) if index == 0 && name.eq_ignore_ascii_case("file") => {},
+            (Some(name), Some(caption)) if !name.is_empty() && !caption.is_empty() => {
+                captions.insert(name.to_owned(), caption.to_owned());
+            },
+            _ => {},
+        }
+    }
+    captions
+}
+
+// a directory's README.md rendered to html, shown at the top of its index page
+pub fn album_description(dir: &Path) -> Option<String> {
+    let markdown = fs::read_to_string(dir.join(README_FILE_NAME)).ok()?;
+    if markdown.trim().is_empty() {
+        return None;
+    }
+    let mut rendered = String::new();
+    html::push_html(&mut rendered, Parser::new_ext(&markdown, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH));
+    Some(rendered)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn caption_sources_in_order() {
+        let root = Path::new("./test_files_out/captions");
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root).unwrap();
+        fs::write(root.join(CAPTIONS_CSV_FILE_NAME), "file,caption\na.jpg,from csv\nb.jpg,\"from csv, quoted\"\nc.jpg,from csv\n").unwrap();
+        fs::write(root.join(CAPTIONS_TOML_FILE_NAME), "\"c.jpg\" = \"from toml\"\n").unwrap();
+        fs::write(root.join("a.jpg.txt"), "from text file\n").unwrap();
+
+        let captions = DirCaptions::load(root);
+        assert_eq!(captions.caption_for(&root.join("a.jpg")).as_deref(), Some("from text file"));
+        assert_eq!(captions.caption_for(&root.join("b.jpg")).as_deref(), Some("from csv, quoted"));
+        assert_eq!(captions.caption_for(&root.join("c.jpg")).as_deref(), Some("from toml"));
+        assert_eq!(captions.caption_for(&root.join("d.jpg")), None);
+        assert_eq!(captions.caption_for(&root.join("file")), None);
+    }
+
+    #[test]
+    fn renders_readme() {
+        let root = Path::new("./test_files_out/readme");
+        fs::create_dir_all(root).unwrap();
+        fs::write(root.join(README_FILE_NAME), "# Holiday\n\nA *week* away").unwrap();
+        assert_eq!(album_description(root).as_deref(), Some("<h1>Holiday</h1>\n<p>A <em>week</em> away</p>\n"));
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    fs,
+    path::{Path,PathBuf},
+};
+
+use super::feed::FEED_FILE_NAME;
+use super::geo::GEOJSON_FILE_NAME;
+use super::html_generation::is_page_file_name;
+use super::logging::LOG_FILE_NAME;
+use super::manifest::MANIFEST_FILE_NAME;
+use super::search::SEARCH_INDEX_FILE_NAME;
+use super::tags::TAGS_DIR_NAME;
+use super::timeline::TIMELINE_DIR_NAME;
+use super::traversal::{EntryKind,Traversal};
+use super::thumbnail::{THUMBNAIL_INFO_FILE_NAME,source_name_for_thumbnail};
+use log::{debug,warn};
+
+// names of the things this program creates inside an album directory
+pub const CACHE_DIR_NAME: &str = "cacheDir";
+pub const LEGACY_CACHE_DIR_NAME: &str = "imageCache";
+pub const INDEX_FILE_NAME: &str = "index.html";
+pub const RESOURCE_FILE_NAMES: [&str; 3] = ["main.js", "spa.js", "styles.css"];
+
+#[derive(Default)]
+pub struct CleanReport {
+    removed: Vec<PathBuf>,
+    bytes_reclaimed: u64,
+}
+
+impl CleanReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_removed(&self) -> &[PathBuf] {
+        &self.removed
+    }
+
+    pub fn get_bytes_reclaimed(&self) -> u64 {
+        self.bytes_reclaimed
+    }
+
+    fn remove(&mut self, path: &Path) {
+        let size = disk_usage(path);
+        let result = if path.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        };
+        match result {
+            Ok(_) => {
+                debug!("removed {:?}", path);
+                self.bytes_reclaimed += size;
+                self.removed.push(path.to_path_buf());
+            },
+            Err(reason) => warn!("{:?} - could not remove: {}", path, reason),
+        }
+    }
+}
+
+// removes everything this program has generated in and below `path`. If
+// `orphans_only` is set, only thumbnails whose source file has gone are removed
+pub fn clean_layer(path: &Path, current_depth: usize, max_depth: usize, orphans_only: bool, traversal: &mut Traversal, report: &mut CleanReport) {
+    if current_depth == max_depth+1 || !traversal.enter_dir(path) {
+        return;
+    }
+
+    let cache_dir_path = path.join(CACHE_DIR_NAME);
+    if orphans_only {
+        prune_orphans(path, &cache_dir_path, report);
+    } else {
+        for artefact in [cache_dir_path, path.join(LEGACY_CACHE_DIR_NAME), path.join(INDEX_FILE_NAME)].iter() {
+            if artefact.exists() {
+                report.remove(artefact);
+            }
+        }
+        // the extra pages of paginated albums
+        let mut page_files : Vec<PathBuf> = match fs::read_dir(path) {
+            Ok(entries) => entries.filter_map(|entry_res| entry_res.ok())
+                .filter(|entry| is_page_file_name(&entry.file_name().to_string_lossy()))
+                .map(|entry| entry.path())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        page_files.sort();
+        for page_file in page_files {
+            report.remove(&page_file);
+        }
+        if current_depth == 0 {
+            // older builds wrote the tag and timeline pages beside the album
+            // rather than in the cacheDir
+            remove_generated_pages(&path.join(TAGS_DIR_NAME), report);
+            remove_generated_pages(&path.join(TIMELINE_DIR_NAME), report);
+            // the map locations, search index and feed are still written
+            // here. The log is a leftover, older builds wrote it beside the
+            // album rather than in the cacheDir
+            for generated in [GEOJSON_FILE_NAME, SEARCH_INDEX_FILE_NAME, FEED_FILE_NAME, LOG_FILE_NAME].iter() {
+                if path.join(generated).exists() {
+                    report.remove(&path.join(generated));
+                }
+            }
+        }
+    }
+
+    // walk the same directories a build would have
+    let mut directories : Vec<PathBuf> = match fs::read_dir(path) {
+        Ok(entries) => entries.filter_map(|entry_res| entry_res.ok())
+            .filter(|entry| traversal.classify(entry) == Some(EntryKind::Dir))
+            .map(|entry| entry.path())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    directories.sort();
+    for directory in directories {
+        let file_name = directory.file_name().unwrap().to_string_lossy().into_owned();
+        // cacheDir has already been handled
+        if file_name != CACHE_DIR_NAME {
+            clean_layer(&directory, current_depth+1, max_depth, orphans_only, traversal, report);
+        }
+    }
+}
+
+// the tag and timeline pages. Only the pages are removed, in case anything
+// else has been put beside them. The directory goes too if that leaves it empty
+fn remove_generated_pages(pages_dir: &Path, report: &mut CleanReport) {
+    let mut pages : Vec<PathBuf> = match fs::read_dir(pages_dir) {
+        Ok(entries) => entries.filter_map(|entry_res| entry_res.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().map(|extension| extension == "html").unwrap_or(false))
+            .collect(),
+        Err(_) => return,
+    };
+    pages.sort();
+    for page in pages {
+        report.remove(&page);
+    }
+    if fs::read_dir(pages_dir).map(|mut entries| entries.next().is_none()).unwrap_or(false) {
+        report.remove(pages_dir);
+    }
+}
+
+fn prune_orphans(path: &Path, cache_dir_path: &Path, report: &mut CleanReport) {
+    let entries = match fs::read_dir(cache_dir_path) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let mut cached_files : Vec<PathBuf> = entries.filter_map(|entry_res| entry_res.ok())
+        .filter(|entry| entry.file_type().map(|t| t.is_file()).unwrap_or(false))
+        .map(|entry| entry.path())
+        .collect();
+    cached_files.sort();
+    for cached_file in cached_files {
+        let cached_name = cached_file.file_name().unwrap().to_string_lossy().into_owned();
+        if RESOURCE_FILE_NAMES.contains(&cached_name.as_str()) || cached_name == MANIFEST_FILE_NAME || cached_name == LOG_FILE_NAME
+            || cached_name == THUMBNAIL_INFO_FILE_NAME {
+            continue;
+        }
+        // detail pages are named after their photo with .html added
+        let source_name = match cached_name.strip_suffix(".html") {
+            Some(source_name) => source_name.to_owned(),
+            None => source_name_for_thumbnail(&cached_name),
+        };
+        if !path.join(source_name).exists() {
+            report.remove(&cached_file);
+        }
+    }
+}
+
+fn disk_usage(path: &Path) -> u64 {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return 0,
+    };
+    if metadata.is_dir() {
+        match fs::read_dir(path) {
+            Ok(entries) => entries.filter_map(|entry_res| entry_res.ok())
+                .map(|entry| disk_usage(&entry.path()))
+                .sum(),
+            Err(_) => 0,
+        }
+    } else {
+        metadata.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::traversal::TraversalPolicy;
+
+    fn make_album(root: &Path) {
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root.join("sub").join(CACHE_DIR_NAME)).unwrap();
+        fs::create_dir_all(root.join(CACHE_DIR_NAME)).unwrap();
+        fs::create_dir_all(root.join(LEGACY_CACHE_DIR_NAME)).unwrap();
+        fs::write(root.join("a.jpg"), b"photo").unwrap();
+        fs::write(root.join(INDEX_FILE_NAME), b"<html>").unwrap();
+        fs::write(root.join("page-2.html"), b"<html>").unwrap();
+        fs::write(root.join(FEED_FILE_NAME), b"<feed>").unwrap();
+        fs::write(root.join(CACHE_DIR_NAME).join("a.jpg"), b"thumb").unwrap();
+        fs::write(root.join(CACHE_DIR_NAME).join("gone.jpg"), b"thumb").unwrap();
+        fs::write(root.join(CACHE_DIR_NAME).join("a.jpg.html"), b"<html>").unwrap();
+        fs::write(root.join(CACHE_DIR_NAME).join("gone.jpg.html"), b"<html>").unwrap();
+        fs::write(root.join(CACHE_DIR_NAME).join("main.js"), b"js").unwrap();
+        fs::write(root.join(CACHE_DIR_NAME).join(MANIFEST_FILE_NAME), b"{}").unwrap();
+        fs::write(root.join(CACHE_DIR_NAME).join(LOG_FILE_NAME), b"log").unwrap();
+        fs::write(root.join(CACHE_DIR_NAME).join(THUMBNAIL_INFO_FILE_NAME), b"{}").unwrap();
+        fs::write(root.join(LOG_FILE_NAME), b"log").unwrap();
+        fs::write(root.join("sub").join(INDEX_FILE_NAME), b"<html>").unwrap();
+        fs::create_dir_all(root.join(TAGS_DIR_NAME)).unwrap();
+        fs::write(root.join(TAGS_DIR_NAME).join("boats.html"), b"<html>").unwrap();
+    }
+
+    #[test]
+    fn full_clean_removes_all_artefacts() {
+        let root = PathBuf::from("./test_files_out/clean_full");
+        make_album(&root);
+        let before = disk_usage(&root);
+        let mut report = CleanReport::new();
+        let mut traversal = Traversal::new(&TraversalPolicy::default(), &root);
+        clean_layer(&root, 0, 5, false, &mut traversal, &mut report);
+        assert!(!root.join(CACHE_DIR_NAME).exists());
+        assert!(!root.join(LEGACY_CACHE_DIR_NAME).exists());
+        assert!(!root.join(INDEX_FILE_NAME).exists());
+        assert!(!root.join("sub").join(CACHE_DIR_NAME).exists());
+        assert!(!root.join("sub").join(INDEX_FILE_NAME).exists());
+        assert!(!root.join("page-2.html").exists());
+        assert!(!root.join(TAGS_DIR_NAME).exists());
+        assert!(!root.join(FEED_FILE_NAME).exists());
+        assert!(!root.join(LOG_FILE_NAME).exists());
+        assert!(root.join("a.jpg").exists());
+        assert_eq!(report.get_removed().len(), 10);
+        // only the photo is left, everything else was reclaimed
+        assert_eq!(disk_usage(&root), disk_usage(&root.join("a.jpg")));
+        assert_eq!(report.get_bytes_reclaimed(), before - disk_usage(&root));
+    }
+
+    #[test]
+    fn prune_only_removes_orphans() {
+        let root = PathBuf::from("./test_files_out/clean_prune");
+        make_album(&root);
+        let mut report = CleanReport::new();
+        let mut traversal = Traversal::new(&TraversalPolicy::default(), &root);
+        clean_layer(&root, 0, 5, true, &mut traversal, &mut report);
+        assert!(root.join(CACHE_DIR_NAME).join("a.jpg").exists());
+        assert!(root.join(CACHE_DIR_NAME).join("main.js").exists());
+        assert!(root.join(CACHE_DIR_NAME).join(MANIFEST_FILE_NAME).exists());
+        assert!(root.join(CACHE_DIR_NAME).join(LOG_FILE_NAME).exists());
+        assert!(root.join(CACHE_DIR_NAME).join(THUMBNAIL_INFO_FILE_NAME).exists());
+        assert!(!root.join(CACHE_DIR_NAME).join("gone.jpg").exists());
+        assert!(root.join(CACHE_DIR_NAME).join("a.jpg.html").exists());
+        assert!(!root.join(CACHE_DIR_NAME).join("gone.jpg.html").exists());
+        assert!(root.join(INDEX_FILE_NAME).exists());
+        assert_eq!(report.get_removed().len(), 2);
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    fs,
+    io,
+    path::Path,
+};
+
+use serde::Serialize;
+
+use super::util::ActionRecord;
+
+// bumped whenever a field is renamed or removed, so other tools can tell
+const EXPORT_VERSION: u32 = 1;
+
+// the whole tree as scanned and rendered, for tools that want the album
+// without rebuilding it
+#[derive(Serialize)]
+pub struct Export<'a> {
+    pub version: u32,
+    pub root:    &'a ActionRecord,
+}
+
+impl<'a> Export<'a> {
+    pub fn new(root: &'a ActionRecord) -> Self {
+        Self { version: EXPORT_VERSION, root }
+    }
+
+    // serialised before the file is created, so a path that isn't utf-8
+    // doesn't leave half an export behind
+    pub fn write_json(&self, export_path: &Path) -> io::Result<()> {
+        let mut json = serde_json::to_vec_pretty(self)?;
+        json.push(b'\n');
+        fs::write(export_path, json)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::util::test::album_fixture;
+
+    #[test]
+    fn exports_the_tree() {
+        let root = album_fixture();
+        let pa = &root.get_subdirs()[0].get_photos()[0];
+        pa.set_dimensions(4000, 3000);
+        pa.set_thumbnail_size(500, 375);
+
+        let value = serde_json::to_value(Export::new(&root)).unwrap();
+        assert_eq!(value["version"], EXPORT_VERSION);
+        let photo = &value["root"]["sub_dirs"][0]["photos"][0];
+        assert_eq!(value["root"]["sub_dirs"][0]["dir"], "/album/trip");
+        assert!(photo.get("dir").is_none());
+        assert_eq!(photo["file"], "boat.jpg");
+        assert_eq!(photo["thumbnail"], "cacheDir/boat.jpg.500w.jpg");
+        assert_eq!(photo["kind"], "image");
+        assert_eq!(photo["dimensions"], serde_json::json!([4000, 3000]));
+        assert_eq!(photo["thumbnail_size"], serde_json::json!([500, 375]));
+        assert_eq!(photo["tags"]["title"], "Harbour");
+        assert_eq!(photo["caption"], "at dusk");
+        assert!(photo["metadata"].is_null());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reports_paths_that_are_not_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let root = ActionRecord::new(Path::new(OsStr::from_bytes(b"/album/r\xffd")));
+        let export_path = PathBuf::from("./test_files_out/export_not_utf8.json");
+        let _ = fs::remove_file(&export_path);
+        assert!(Export::new(&root).write_json(&export_path).is_err());
+        assert!(!export_path.exists());
+    }
+}
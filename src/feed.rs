@@ -0,0 +1,175 @@
+use std::{
+    fs,
+    io,
+    path::PathBuf,
+};
+
+use chrono::{DateTime,SecondsFormat,Utc};
+
+use super::html_builder::escape_html;
+use super::html_generation::get_first_photo;
+use super::options::{BuildOptions,OutputTarget};
+use super::util::{ActionRecord,relative};
+
+// written to the top of the tree
+pub const FEED_FILE_NAME: &str = "feed.xml";
+// who the feed is credited to when no author is given, as atom requires one
+pub const DEFAULT_FEED_AUTHOR: &str = "album_maker";
+// the most recently changed albums listed
+const MAX_FEED_ENTRIES: usize = 20;
+
+// an album in the feed, with links relative to the top of the tree
+#[derive(Debug)]
+pub struct FeedEntry {
+    pub title:   String,
+    pub link:    String,
+    pub cover:   Option<String>,
+    pub photos:  usize,
+    // when a photo directly in the album, or the album itself, was last
+    // changed. The album's own time moves when photos are removed or renamed
+    pub updated: DateTime<Utc>,
+}
+
+// every album holding photos, most recently changed first
+pub fn collect_entries(root: &ActionRecord, output: OutputTarget) -> Vec<FeedEntry> {
+    let root_path = root.get_path();
+    let mut entries : Vec<FeedEntry> = root.get_all_records().into_iter()
+        .filter_map(|ar| {
+            let photos_updated = ar.get_photos().iter()
+                .filter_map(|pa| pa.get_abs_actual().metadata().ok()?.modified().ok())
+                .max()?;
+            let updated = ar.get_modified().map_or(photos_updated, |album_updated| album_updated.max(photos_updated));
+            let path = relative(&root_path, &ar.get_path());
+            let link = match (output, path.is_empty()) {
+                (OutputTarget::Html, true)  => String::from("index.html"),
+                (OutputTarget::Html, false) => format!("{}/index.html", url_path(&path)),
+                (OutputTarget::Spa, _)      => format!("index.html#/{}", url_path(&path)),
+            };
+            Some(FeedEntry {
+                title:   if path.is_empty() { String::from("Photo Album") } else { path },
+                link,
+                cover:   get_first_photo(ar).map(|pa| url_path(&relative(&root_path, &pa.get_abs_downsized()))),
+                photos:  ar.get_photos().len(),
+                updated: DateTime::<Utc>::from(updated),
+            })
+        })
+        .collect();
+    entries.sort_by(|a, b| b.updated.cmp(&a.updated).then_with(|| a.title.cmp(&b.title)));
+    entries.truncate(MAX_FEED_ENTRIES);
+    entries
+}
+
+// percent encodes everything in a path but letters, digits, slashes and -._~
+fn url_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+// the feed as atom xml, credited to `author`. Links are made absolute with
+// `base_url` when it is given, feed readers otherwise resolve them against
+// where the feed was found
+pub fn render_feed(entries: &[FeedEntry], base_url: Option<&str>, author: &str) -> String {
+    let url = |path: &str| match base_url {
+        Some(base_url) => format!("{}/{}", base_url.trim_end_matches('/'), path),
+        None => path.to_owned(),
+    };
+    // atom wants a permanent id for the feed and each entry, the album's own
+    // address is used where there is one
+    let id = |path: &str| match base_url {
+        Some(_) => url(path),
+        None => format!("urn:album:{}", path),
+    };
+    let timestamp = |time: &DateTime<Utc>| time.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let updated = entries.iter().map(|entry| entry.updated).max().unwrap_or_else(Utc::now);
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    xml.push_str("  <title>Photo Album</title>\n");
+    xml.push_str(&format!("  <id>{}</id>\n", escape_html(&id(FEED_FILE_NAME))));
+    xml.push_str(&format!("  <link rel=\"self\" href=\"{}\"/>\n", escape_html(&url(FEED_FILE_NAME))));
+    xml.push_str(&format!("  <link rel=\"alternate\" type=\"text/html\" href=\"{}\"/>\n", escape_html(&url("index.html"))));
+    xml.push_str(&format!("  <updated>{}</updated>\n", timestamp(&updated)));
+    xml.push_str(&format!("  <author><name>{}</name></author>\n", escape_html(author)));
+    xml.push_str("  <generator>album_maker</generator>\n");
+    for entry in entries {
+        let link = url(&entry.link);
+        let mut content = String::new();
+        if let Some(cover) = &entry.cover {
+            content.push_str(&format!("<a href=\"{}\"><img src=\"{}\" alt=\"{}\"/></a>",
+                escape_html(&link), escape_html(&url(cover)), escape_html(&entry.title)));
+        }
+        let summary = format!("{} photo{}", entry.photos, if entry.photos == 1 { "" } else { "s" });
+        content.push_str(&format!("<p>{}</p>", summary));
+
+        xml.push_str("  <entry>\n");
+        xml.push_str(&format!("    <title>{}</title>\n", escape_html(&entry.title)));
+        xml.push_str(&format!("    <id>{}</id>\n", escape_html(&id(&entry.link))));
+        xml.push_str(&format!("    <link rel=\"alternate\" type=\"text/html\" href=\"{}\"/>\n", escape_html(&link)));
+        xml.push_str(&format!("    <updated>{}</updated>\n", timestamp(&entry.updated)));
+        xml.push_str(&format!("    <summary>{}</summary>\n", summary));
+        // the content is html, escaped once more to sit inside the xml
+        xml.push_str(&format!("    <content type=\"html\">{}</content>\n", escape_html(&content)));
+        xml.push_str("  </entry>\n");
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+pub fn feed_path(root: &ActionRecord) -> PathBuf {
+    root.get_path().join(FEED_FILE_NAME)
+}
+
+// writes the feed to the top of the tree, returning how many albums it lists
+pub fn write_feed(root: &ActionRecord, options: &BuildOptions) -> io::Result<usize> {
+    let entries = collect_entries(root, options.output);
+    fs::write(feed_path(root), render_feed(&entries, options.base_url.as_deref(), &options.feed_author))?;
+    Ok(entries.len())
+}
+
+// removes the feed of the last build, for when this one has none
+pub fn clear_feed(root: &ActionRecord) {
+    let feed_path = feed_path(root);
+    if feed_path.exists() {
+        fs::remove_file(&feed_path).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn entry(title: &str, cover: Option<&str>, day: u32) -> FeedEntry {
+        FeedEntry {
+            title:   title.to_owned(),
+            link:    format!("{}/index.html", url_path(title)),
+            cover:   cover.map(str::to_owned),
+            photos:  1,
+            updated: Utc.with_ymd_and_hms(2021, 6, day, 12, 0, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn renders_atom() {
+        let entries = [entry("trip & co", Some("trip/cacheDir/a.jpg.500w.jpg"), 2), entry("home", None, 1)];
+        let xml = render_feed(&entries, Some("https://example.com/photos/"), "Ann & Bo");
+        assert!(xml.contains("<updated>2021-06-02T12:00:00Z</updated>"));
+        assert!(xml.contains("<author><name>Ann &amp; Bo</name></author>"));
+        assert!(xml.contains("<link rel=\"self\" href=\"https://example.com/photos/feed.xml\"/>"));
+        assert!(xml.contains("<title>trip &amp; co</title>"));
+        assert!(xml.contains("<id>https://example.com/photos/trip%20%26%20co/index.html</id>"));
+        assert!(xml.contains("&lt;img src=&quot;https://example.com/photos/trip/cacheDir/a.jpg.500w.jpg&quot;"));
+        assert_eq!(xml.matches("<entry>").count(), 2);
+
+        let xml = render_feed(&entries, None, DEFAULT_FEED_AUTHOR);
+        assert!(xml.contains("<author><name>album_maker</name></author>"));
+        assert!(xml.contains("<id>urn:album:home/index.html</id>"));
+        assert!(xml.contains("href=\"home/index.html\""));
+        assert_eq!(url_path("café/a b.jpg"), "caf%C3%A9/a%20b.jpg");
+    }
+}
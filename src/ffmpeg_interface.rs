@@ -1,6 +1,12 @@
-
+// explicit returns are this file's style
+#![allow(clippy::needless_return)]
+use std::fs;
 use std::process::Command;
 use std::path::Path;
+use log::debug;
+use super::media::{self,MediaKind};
+use super::progress::RenderOutcome;
+use super::thumbnail::{self,ThumbnailInfo,ThumbnailSettings};
 
 
 pub fn ffmpeg_available() -> bool {
@@ -20,23 +26,41 @@ fn command_available(cmd: &str) -> bool {
     return false;
 } 
 
-pub fn create_thumbnail<P: AsRef<Path>>(input_file: P, output_file: P, width: u32) {
-
+// ffmpegthumbnailer only grabs a frame, at its full size. It is then scaled,
+// cropped and encoded like any photo, so the thumbnail matches its name
+pub fn create_thumbnail<P: AsRef<Path>>(input_file: P, output_file: P, settings: &ThumbnailSettings) -> (RenderOutcome, Option<ThumbnailInfo>) {
+    let frame_file = output_file.as_ref().with_extension("frame.png");
     let command_output = Command::new("ffmpegthumbnailer")
-                            .args(&["-i", input_file.as_ref().to_str().unwrap()])
-                            .args(&["-o", output_file.as_ref().to_str().unwrap()])
-                            .arg(format!("-s {}", width))
+                            .args(["-i", input_file.as_ref().to_str().unwrap()])
+                            .args(["-o", frame_file.to_str().unwrap()])
+                            .args(["-s", "0"])
                             .output();
 
-    if command_output.is_ok() {
-        println!("> {:?} - rendering", input_file.as_ref().file_name().unwrap());
-    } else {
-        println!("> {:?} - could not process", input_file.as_ref().file_name().unwrap());
-    }
+    let outcome = match command_output {
+        Ok(output) if output.status.success() => match image::open(&frame_file) {
+            Ok(frame) => {
+                let rendered = thumbnail::render(&frame, settings);
+                match thumbnail::encode(&rendered, output_file.as_ref(), settings) {
+                    Ok(_) => (RenderOutcome::Rendered, Some(thumbnail::describe(&rendered))),
+                    Err(reason) => {
+                        debug!("{:?} - {}", output_file.as_ref(), reason);
+                        (RenderOutcome::Failed, None)
+                    },
+                }
+            },
+            Err(reason) => {
+                debug!("{:?} - {}", frame_file, reason);
+                (RenderOutcome::Failed, None)
+            },
+        },
+        _ => (RenderOutcome::Failed, None),
+    };
+    let _ = fs::remove_file(&frame_file);
+    return outcome;
 }
 
 // wrapper to match the util non-ffmpeg function 
-pub fn downsize_image<P: AsRef<Path>>(input_file: P, output_file: P, width: u32, force_regen:bool) {
+pub fn downsize_image<P: AsRef<Path>>(input_file: P, output_file: P, settings: &ThumbnailSettings, force_regen:bool) -> (RenderOutcome, Option<ThumbnailInfo>) {
     let in_file = input_file.as_ref();
     let out_file = output_file.as_ref();
     if !force_regen && in_file.exists() && out_file.exists() {
@@ -47,18 +71,21 @@ pub fn downsize_image<P: AsRef<Path>>(input_file: P, output_file: P, width: u32,
             // the file to be converted already seems to have been converted
             // the downsized file is newer than the original, so lets not
             // waste time
-            println!("> {:?} - up to date", in_file.file_name().unwrap());
-            return;
+            return (RenderOutcome::UpToDate, None);
         }
     }
-    create_thumbnail(in_file, out_file, width); 
+    create_thumbnail(in_file, out_file, settings)
 }
 
 
-// its ffmpeg, its probably compatible
-#[allow(dead_code)]
-pub fn is_compatible<P: AsRef<Path>>(_input_file: P) -> bool {
-    unimplemented!();
+// ffmpegthumbnailer can grab a frame from anything ffmpeg can decode as video,
+// which includes still images
+pub fn is_compatible<P: AsRef<Path>>(input_file: P) -> bool {
+    is_compatible_kind(media::identify(input_file.as_ref()).0)
+}
+
+pub fn is_compatible_kind(kind: MediaKind) -> bool {
+    matches!(kind, MediaKind::Video | MediaKind::Image)
 }
 
 
@@ -67,8 +94,16 @@ mod test {
     use super::*;
     #[test]
     fn command_available_test() {
-        assert_eq!(command_available("not_command"),false);
-        assert_eq!(command_available("echo"),true);
+        assert!(!command_available("not_command"));
+        assert!(command_available("echo"));
+    }
+
+    #[test]
+    fn compatible_kinds() {
+        assert!(is_compatible_kind(MediaKind::Video));
+        assert!(is_compatible_kind(MediaKind::Image));
+        assert!(!is_compatible_kind(MediaKind::Document));
+        assert!(!is_compatible_kind(MediaKind::Unknown));
     }
 
 }
@@ -0,0 +1,136 @@
+use std::path::{Path,PathBuf};
+
+use globset::{GlobBuilder,GlobSet,GlobSetBuilder};
+use ignore::{
+    Match,
+    gitignore::{Gitignore,GitignoreBuilder},
+};
+use log::{debug,warn};
+
+pub const IGNORE_FILE_NAME: &str = ".albumignore";
+
+// decides which files and folders make it into the album, from --include and
+// --exclude globs on the command line plus any .albumignore files in the tree
+pub struct Filters {
+    root:     PathBuf,
+    includes: Option<GlobSet>,
+    excludes: GlobSet,
+    // one entry per directory currently being walked, innermost last
+    ignores:  Vec<Option<Gitignore>>,
+}
+
+impl Filters {
+    // globs are matched against paths relative to `root`, and against the bare
+    // file name, so "*.CR2" or "Screenshots" apply at any depth
+    pub fn new(root: &Path, includes: &[String], excludes: &[String]) -> Result<Self, String> {
+        Ok(Self {
+            root:     root.to_path_buf(),
+            includes: if includes.is_empty() { None } else { Some(build_glob_set(includes)?) },
+            excludes: build_glob_set(excludes)?,
+            ignores:  Vec::new(),
+        })
+    }
+
+    // called when starting on a directory, picks up its .albumignore if present
+    pub fn enter_dir(&mut self, dir: &Path) {
+        let ignore_file = dir.join(IGNORE_FILE_NAME);
+        if !ignore_file.is_file() {
+            self.ignores.push(None);
+            return;
+        }
+        let mut builder = GitignoreBuilder::new(dir);
+        if let Some(reason) = builder.add(&ignore_file) {
+            warn!("{:?} - {}", ignore_file, reason);
+        }
+        match builder.build() {
+            Ok(gitignore) => self.ignores.push(Some(gitignore)),
+            Err(reason) => {
+                warn!("{:?} - {}", ignore_file, reason);
+                self.ignores.push(None);
+            },
+        }
+    }
+
+    pub fn leave_dir(&mut self) {
+        self.ignores.pop();
+    }
+
+    pub fn allows(&self, path: &Path, is_dir: bool) -> bool {
+        let relative_path = path.strip_prefix(&self.root).unwrap_or(path);
+        let file_name = Path::new(path.file_name().unwrap_or_default());
+        let matches = |set: &GlobSet| set.is_match(relative_path) || set.is_match(file_name);
+
+        if matches(&self.excludes) {
+            debug!("{:?} - excluded", path);
+            return false;
+        }
+        // includes only narrow down files, folders are always walked into
+        if let Some(includes) = &self.includes {
+            if !is_dir && !matches(includes) {
+                debug!("{:?} - not included", path);
+                return false;
+            }
+        }
+        // the closest .albumignore with an opinion wins, as with .gitignore
+        for gitignore in self.ignores.iter().rev().flatten() {
+            match gitignore.matched(path, is_dir) {
+                Match::Ignore(_) => {
+                    debug!("{:?} - ignored by {}", path, IGNORE_FILE_NAME);
+                    return false;
+                },
+                Match::Whitelist(_) => return true,
+                Match::None => {},
+            }
+        }
+        true
+    }
+}
+
+// case insensitive, as cameras name files IMG_0001.JPG
+fn build_glob_set(globs: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        let glob = GlobBuilder::new(glob)
+            .case_insensitive(true)
+            .build()
+            .map_err(|reason| format!("{:?} - {}", glob, reason))?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|reason| reason.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn applies_globs_and_ignore_files() {
+        fs::create_dir_all("./test_files_out/filters/trip").unwrap();
+        let root = PathBuf::from("./test_files_out/filters").canonicalize().unwrap();
+        fs::write(root.join(IGNORE_FILE_NAME), "private/\n*.png\n").unwrap();
+        fs::write(root.join("trip").join(IGNORE_FILE_NAME), "!keep.png\n").unwrap();
+
+        let mut filters = Filters::new(&root,
+            &[String::from("*.jpg"), String::from("*.png")],
+            &[String::from("Screenshots")]).unwrap();
+        filters.enter_dir(&root);
+        assert!(filters.allows(&root.join("a.jpg"), false));
+        assert!(filters.allows(&root.join("IMG_0001.JPG"), false));
+        assert!(!filters.allows(&root.join("a.CR2"), false));
+        assert!(!filters.allows(&root.join("a.png"), false));
+        assert!(!filters.allows(&root.join("private"), true));
+        assert!(!filters.allows(&root.join("Screenshots"), true));
+        assert!(!filters.allows(&root.join("screenshots"), true));
+        assert!(filters.allows(&root.join("trip"), true));
+
+        filters.enter_dir(&root.join("trip"));
+        assert!(!filters.allows(&root.join("trip").join("Screenshots"), true));
+        assert!(!filters.allows(&root.join("trip").join("other.png"), false));
+        assert!(filters.allows(&root.join("trip").join("keep.png"), false));
+        filters.leave_dir();
+        assert!(!filters.allows(&root.join("keep.png"), false));
+
+        assert!(Filters::new(&root, &[String::from("[")], &[]).is_err());
+    }
+}
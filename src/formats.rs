@@ -0,0 +1,241 @@
+use std::{
+    fs::File,
+    io::{BufRead,BufReader,Read},
+    path::Path,
+};
+
+use super::media;
+use image::{DynamicImage,ImageDecoder,ImageFormat,codecs::jpeg::JpegDecoder};
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum PhotoFormat {
+    Jpeg,
+    Png,
+    Gif,
+    WebP,
+    Tiff,
+    Bmp,
+    Avif,
+    Heif,
+}
+
+pub const ALL_FORMATS: [PhotoFormat; 8] = [
+    PhotoFormat::Jpeg, PhotoFormat::Png, PhotoFormat::Gif, PhotoFormat::WebP,
+    PhotoFormat::Tiff, PhotoFormat::Bmp, PhotoFormat::Avif, PhotoFormat::Heif,
+];
+
+impl PhotoFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PhotoFormat::Jpeg => "jpeg",
+            PhotoFormat::Png  => "png",
+            PhotoFormat::Gif  => "gif",
+            PhotoFormat::WebP => "webp",
+            PhotoFormat::Tiff => "tiff",
+            PhotoFormat::Bmp  => "bmp",
+            PhotoFormat::Avif => "avif",
+            PhotoFormat::Heif => "heif",
+        }
+    }
+
+    // whether this build can decode the format without ffmpeg
+    pub fn decodable_locally(&self) -> bool {
+        match self {
+            PhotoFormat::Jpeg | PhotoFormat::Png => true,
+            PhotoFormat::Gif  => cfg!(feature = "gif"),
+            PhotoFormat::WebP => cfg!(feature = "webp"),
+            PhotoFormat::Tiff => cfg!(feature = "tiff"),
+            PhotoFormat::Bmp  => cfg!(feature = "bmp"),
+            PhotoFormat::Heif => cfg!(feature = "heif"),
+            PhotoFormat::Avif => false,
+        }
+    }
+
+    // a line for the --formats report explaining how the format is handled
+    pub fn capability(&self) -> &'static str {
+        match self {
+            PhotoFormat::Jpeg | PhotoFormat::Png => "built in",
+            PhotoFormat::Gif if self.decodable_locally() => "enabled, animated gifs keep their animation",
+            PhotoFormat::WebP if self.decodable_locally() => "enabled, lossy images only",
+            PhotoFormat::Avif => "no local decoder, thumbnailed by ffmpegthumbnailer when installed",
+            _ if self.decodable_locally() => "enabled",
+            _ => "not compiled in, rebuild with the cargo feature of the same name",
+        }
+    }
+
+    fn image_format(&self) -> Option<ImageFormat> {
+        match self {
+            PhotoFormat::Jpeg => Some(ImageFormat::Jpeg),
+            PhotoFormat::Png  => Some(ImageFormat::Png),
+            PhotoFormat::Gif  => Some(ImageFormat::Gif),
+            PhotoFormat::WebP => Some(ImageFormat::WebP),
+            PhotoFormat::Tiff => Some(ImageFormat::Tiff),
+            PhotoFormat::Bmp  => Some(ImageFormat::Bmp),
+            PhotoFormat::Avif | PhotoFormat::Heif => None,
+        }
+    }
+}
+
+// works out the format of an image from its header, or its extension
+pub fn detect_format(path: &Path) -> Option<PhotoFormat> {
+    media::identify(path).1
+}
+
+// whether a gif has more than one frame. Only the block structure is walked,
+// stopping at the second frame, so nothing is decoded
+pub fn is_animated_gif(path: &Path) -> bool {
+    match File::open(path) {
+        Ok(file) => count_gif_frames(&mut BufReader::new(file), 2) >= 2,
+        Err(_) => false,
+    }
+}
+
+fn count_gif_frames<R: BufRead>(reader: &mut R, limit: usize) -> usize {
+    let mut byte = [0u8; 1];
+    let mut read_byte = |reader: &mut R| reader.read_exact(&mut byte).ok().map(|_| byte[0]);
+    // the signature and logical screen descriptor, then any global colour table
+    let mut screen = [0u8; 13];
+    if reader.read_exact(&mut screen).is_err() || !screen.starts_with(b"GIF") {
+        return 0;
+    }
+    if !skip(reader, colour_table_length(screen[10])) {
+        return 0;
+    }
+    let mut frames = 0;
+    while frames < limit {
+        match read_byte(reader) {
+            // an extension, its label then data sub-blocks
+            Some(0x21) => {
+                if read_byte(reader).is_none() || !skip_sub_blocks(reader) {
+                    break;
+                }
+            },
+            // an image descriptor, any local colour table, the lzw code size then data sub-blocks
+            Some(0x2C) => {
+                frames += 1;
+                let mut descriptor = [0u8; 9];
+                if reader.read_exact(&mut descriptor).is_err()
+                    || !skip(reader, colour_table_length(descriptor[8]) + 1)
+                    || !skip_sub_blocks(reader) {
+                    break;
+                }
+            },
+            _ => break,
+        }
+    }
+    frames
+}
+
+fn colour_table_length(packed: u8) -> u64 {
+    if packed & 0x80 == 0 { 0 } else { 3 << ((packed & 0x07) + 1) }
+}
+
+fn skip<R: Read>(reader: &mut R, length: u64) -> bool {
+    std::io::copy(&mut reader.take(length), &mut std::io::sink()).map(|copied| copied == length).unwrap_or(false)
+}
+
+fn skip_sub_blocks<R: Read>(reader: &mut R) -> bool {
+    let mut size = [0u8; 1];
+    loop {
+        if reader.read_exact(&mut size).is_err() {
+            return false;
+        }
+        if size[0] == 0 {
+            return true;
+        }
+        if !skip(reader, size[0] as u64) {
+            return false;
+        }
+    }
+}
+
+// decodes a still image with whichever local decoder suits its format
+pub fn open_image(path: &Path) -> Result<DynamicImage, String> {
+    let format = detect_format(path).ok_or_else(|| String::from("unrecognised image format"))?;
+    if !format.decodable_locally() {
+        return Err(format!("no {} decoder in this build", format.name()));
+    }
+    match format {
+        PhotoFormat::Heif => open_heif(path),
+        _ => {
+            let file = File::open(path).map_err(|reason| reason.to_string())?;
+            image::load(std::io::BufReader::new(file), format.image_format().unwrap())
+                .map_err(|reason| reason.to_string())
+        },
+    }
+}
+
+// decodes an image that is about to be shrunk, `target_size` giving the size it
+// will be shrunk to from its full size. Jpegs are decoded at a reduced scale that
+// still leaves at least that many pixels, which is much quicker than decoding
+// every pixel of a large photo
+pub fn open_image_scaled<F: Fn(u32, u32) -> (u32, u32)>(path: &Path, target_size: F) -> Result<DynamicImage, String> {
+    if detect_format(path) != Some(PhotoFormat::Jpeg) {
+        return open_image(path);
+    }
+    let file = File::open(path).map_err(|reason| reason.to_string())?;
+    let mut decoder = JpegDecoder::new(BufReader::new(file)).map_err(|reason| reason.to_string())?;
+    let (full_width, full_height) = decoder.dimensions();
+    let (width, height) = target_size(full_width, full_height);
+    decoder.scale(width.min(u16::MAX as u32) as u16, height.min(u16::MAX as u32) as u16)
+        .map_err(|reason| reason.to_string())?;
+    DynamicImage::from_decoder(decoder).map_err(|reason| reason.to_string())
+}
+
+#[cfg(feature = "heif")]
+fn open_heif(path: &Path) -> Result<DynamicImage, String> {
+    use libheif_rs::{ColorSpace,HeifContext,LibHeif,RgbChroma};
+
+    let lib_heif = LibHeif::new();
+    let path_str = path.to_str().ok_or_else(|| String::from("path is not valid utf-8"))?;
+    let context = HeifContext::read_from_file(path_str).map_err(|reason| reason.to_string())?;
+    let handle = context.primary_image_handle().map_err(|reason| reason.to_string())?;
+    let decoded = lib_heif.decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)
+        .map_err(|reason| reason.to_string())?;
+    let planes = decoded.planes();
+    let plane = planes.interleaved.ok_or_else(|| String::from("no interleaved rgb plane"))?;
+
+    // rows may be padded, copy out just the pixels
+    let row_length = plane.width as usize*3;
+    let mut pixels = Vec::with_capacity(row_length*plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        pixels.extend_from_slice(&row[..row_length]);
+    }
+    image::RgbImage::from_raw(plane.width, plane.height, pixels)
+        .map(DynamicImage::ImageRgb8)
+        .ok_or_else(|| String::from("decoded image has the wrong size"))
+}
+
+#[cfg(not(feature = "heif"))]
+fn open_heif(_path: &Path) -> Result<DynamicImage, String> {
+    Err(String::from("no heif decoder in this build"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_gif_frames() {
+        // a 1x1 gif with a two colour global table, a graphic control extension and one frame
+        let frame : &[u8] = b"\x21\xF9\x04\x00\x0A\x00\x00\x00\x2C\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02\x44\x01\x00";
+        let header : &[u8] = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xFF\xFF\xFF";
+        let still = [header, frame, b"\x3B"].concat();
+        let animated = [header, frame, frame, b"\x3B"].concat();
+        assert_eq!(count_gif_frames(&mut &still[..], 2), 1);
+        assert_eq!(count_gif_frames(&mut &animated[..], 2), 2);
+        // a truncated file counts the frames begun before it ends
+        assert_eq!(count_gif_frames(&mut &animated[..30], 2), 1);
+        assert_eq!(count_gif_frames(&mut &b"\x89PNG\r\n\x1a\n"[..], 2), 0);
+    }
+
+    #[test]
+    fn reports_capabilities() {
+        assert!(PhotoFormat::Jpeg.decodable_locally());
+        assert!(!PhotoFormat::Avif.decodable_locally());
+        assert_eq!(PhotoFormat::Heif.decodable_locally(), cfg!(feature = "heif"));
+        for format in ALL_FORMATS.iter() {
+            assert!(!format.capability().is_empty());
+        }
+    }
+}
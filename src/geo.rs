@@ -0,0 +1,162 @@
+use std::{
+    fs,
+    io,
+    path::{Path,PathBuf},
+};
+
+use serde::Serialize;
+use serde_json::json;
+
+use super::html_builder::escape_html;
+use super::util::{ActionRecord,PhotoAction,relative};
+
+// every located photo in the tree, written to the top directory
+pub const GEOJSON_FILE_NAME: &str = "locations.geojson";
+// no tile server is used unless one is given, leaving only the static map,
+// which needs no network and sends nothing about the album's readers elsewhere
+pub const DEFAULT_TILE_URL: &str = "";
+
+// the size of the static map, which is scaled to fit the page
+const SVG_WIDTH: f64 = 800.0;
+const SVG_HEIGHT: f64 = 400.0;
+const SVG_MARGIN: f64 = 20.0;
+
+// a marker on a map, with paths relative to the page it is on
+#[derive(Clone,Debug,Serialize)]
+pub struct MapPoint {
+    pub lat:       f64,
+    pub lon:       f64,
+    pub label:     String,
+    pub href:      String,
+    pub thumbnail: String,
+}
+
+pub fn photo_location(pa: &PhotoAction) -> Option<(f64, f64)> {
+    pa.get_metadata().and_then(|metadata| metadata.location)
+}
+
+// an album's place on the map of all albums, the middle of its located photos.
+// `index_path` is its index page as seen from the top of the tree
+pub fn album_point(ar: &ActionRecord, index_path: &Path, label: &str, base: &Path) -> Option<MapPoint> {
+    let located : Vec<(&PhotoAction, (f64, f64))> = ar.get_photos().iter()
+        .filter_map(|pa| photo_location(pa).map(|location| (pa, location)))
+        .collect();
+    let (cover, _) = located.first()?;
+    let count = located.len() as f64;
+    Some(MapPoint {
+        lat:       located.iter().map(|(_, (lat, _))| lat).sum::<f64>()/count,
+        lon:       located.iter().map(|(_, (_, lon))| lon).sum::<f64>()/count,
+        label:     label.to_owned(),
+        href:      index_path.to_string_lossy().into_owned(),
+        thumbnail: base.join(cover.get_downsized()).to_string_lossy().into_owned(),
+    })
+}
+
+pub fn geojson_path(root: &ActionRecord) -> PathBuf {
+    root.get_path().join(GEOJSON_FILE_NAME)
+}
+
+// writes the location of every photo in the tree as a geojson feature
+// collection, returning how many photos were located
+pub fn write_geojson(root: &ActionRecord) -> io::Result<usize> {
+    let root_path = root.get_path();
+    let features : Vec<serde_json::Value> = root.get_all_photos().into_iter()
+        .filter_map(|pa| photo_location(pa).map(|location| (pa, location)))
+        .map(|(pa, (lat, lon))| {
+            json!({
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": [lon, lat] },
+                "properties": {
+                    "name":      pa.get_name(),
+                    "path":      relative(&root_path, &pa.get_abs_actual()),
+                    "thumbnail": relative(&root_path, &pa.get_abs_downsized()),
+                    "caption":   pa.get_caption(),
+                },
+            })
+        })
+        .collect();
+    let count = features.len();
+    let collection = json!({ "type": "FeatureCollection", "features": features });
+    fs::write(geojson_path(root), serde_json::to_vec_pretty(&collection)?)?;
+    Ok(count)
+}
+
+// removes the locations of the last build, for when this one has no map
+pub fn clear_geojson(root: &ActionRecord) {
+    let geojson_path = geojson_path(root);
+    if geojson_path.exists() {
+        fs::remove_file(&geojson_path).unwrap();
+    }
+}
+
+// web mercator, as the tiles use, scaled to 0..1 across the world
+fn project(lat: f64, lon: f64) -> (f64, f64) {
+    let lat = lat.clamp(-85.0, 85.0).to_radians();
+    let x = (lon + 180.0)/360.0;
+    let y = (1.0 - (lat.tan() + 1.0/lat.cos()).ln()/std::f64::consts::PI)/2.0;
+    (x, y)
+}
+
+// a map with no tiles, just the points on a plain background, drawn to fit
+// their bounds. Shown when there is no tile server or it can't be reached
+pub fn map_svg(points: &[MapPoint]) -> String {
+    let projected : Vec<(f64, f64)> = points.iter().map(|point| project(point.lat, point.lon)).collect();
+    let min_x = projected.iter().map(|(x, _)| *x).fold(f64::INFINITY, f64::min);
+    let max_x = projected.iter().map(|(x, _)| *x).fold(f64::NEG_INFINITY, f64::max);
+    let min_y = projected.iter().map(|(_, y)| *y).fold(f64::INFINITY, f64::min);
+    let max_y = projected.iter().map(|(_, y)| *y).fold(f64::NEG_INFINITY, f64::max);
+    // one scale for both axes so distances aren't distorted
+    let span = (max_x - min_x).max(max_y - min_y);
+    let scale = if span > 0.0 {
+        ((SVG_WIDTH - 2.0*SVG_MARGIN)/(max_x - min_x).max(f64::EPSILON))
+            .min((SVG_HEIGHT - 2.0*SVG_MARGIN)/(max_y - min_y).max(f64::EPSILON))
+    } else {
+        0.0
+    };
+    let (centre_x, centre_y) = ((min_x + max_x)/2.0, (min_y + max_y)/2.0);
+
+    let mut svg = format!("<svg class=\"map_svg\" viewBox=\"0 0 {} {}\" xmlns=\"http://www.w3.org/2000/svg\">\n", SVG_WIDTH, SVG_HEIGHT);
+    svg.push_str(&format!("<rect class=\"map_background\" width=\"{}\" height=\"{}\"/>\n", SVG_WIDTH, SVG_HEIGHT));
+    for (point, (x, y)) in points.iter().zip(projected) {
+        svg.push_str(&format!(
+            "<a href=\"{}\"><circle class=\"map_point\" cx=\"{:.1}\" cy=\"{:.1}\" r=\"6\"><title>{}</title></circle></a>\n",
+            escape_html(&point.href),
+            SVG_WIDTH/2.0 + (x - centre_x)*scale,
+            SVG_HEIGHT/2.0 + (y - centre_y)*scale,
+            escape_html(&point.label)));
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn point(lat: f64, lon: f64, label: &str) -> MapPoint {
+        MapPoint { lat, lon, label: label.to_owned(), href: format!("{}.jpg", label), thumbnail: String::new() }
+    }
+
+    #[test]
+    fn draws_points_within_the_map() {
+        let svg = map_svg(&[point(51.5, -0.1, "london"), point(48.9, 2.35, "paris & co")]);
+        assert!(svg.contains("<title>paris &amp; co</title>"));
+        let circles : Vec<(f64, f64)> = svg.lines()
+            .filter(|line| line.contains("<circle"))
+            .map(|line| {
+                let value = |name: &str| {
+                    let start = line.find(&format!("{}=\"", name)).unwrap() + name.len() + 2;
+                    line[start..].split('"').next().unwrap().parse::<f64>().unwrap()
+                };
+                (value("cx"), value("cy"))
+            })
+            .collect();
+        // london is north west of paris
+        assert!(circles[0].0 < circles[1].0 && circles[0].1 < circles[1].1);
+        for (x, y) in circles {
+            assert!((0.0..=SVG_WIDTH).contains(&x) && (0.0..=SVG_HEIGHT).contains(&y));
+        }
+        // a single point sits in the middle
+        assert!(map_svg(&[point(0.0, 0.0, "null island")]).contains("cx=\"400.0\" cy=\"200.0\""));
+    }
+}
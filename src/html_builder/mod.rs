@@ -1,3 +1,5 @@
+// explicit returns are this file's style
+#![allow(clippy::needless_return)]
 use std::{
     fmt::{Debug},
     iter::FromIterator,
@@ -31,10 +33,11 @@ impl HtmlDom {
 pub enum HtmlElementType {
     P, A, Ul, Li, Head, Body, Br,
     Link, Img, Script,Title,
-    Div, 
+    Div, Noscript,
 }
 
 #[derive(Debug)]
+#[allow(dead_code)]
 pub struct CssClass {
     pub name: String,
 }
@@ -50,6 +53,14 @@ pub struct HtmlElement {
     href:         Option<String>,
     text:         Option<String>,
     rel:          Option<String>,
+    style:        Option<String>,
+    width:        Option<u32>,
+    height:       Option<u32>,
+    // anything without a field of its own, only rendered on images, links,
+    // divs and scripts
+    attributes:   Vec<(String, String)>,
+    // markup written into a div as it is, unlike text which is escaped
+    html:         Option<String>,
 }
 
 
@@ -65,6 +76,11 @@ impl HtmlElement {
             href:         None,
             text:         None,
             rel:          None,
+            style:        None,
+            width:        None,
+            height:       None,
+            attributes:   Vec::new(),
+            html:         None,
         }
     }
     pub fn set_src(mut self, src: &str) -> Self {
@@ -99,6 +115,25 @@ impl HtmlElement {
         self.text = Some(text.to_owned());
         return self;
     }
+    pub fn set_html(mut self, html: &str) -> Self {
+        self.html = Some(html.to_owned());
+        return self;
+    }
+    pub fn set_style(mut self, style: &str) -> Self {
+        self.style = Some(style.to_owned());
+        return self;
+    }
+    pub fn set_attribute(mut self, name: &str, value: &str) -> Self {
+        self.attributes.push((name.to_owned(), value.to_owned()));
+        return self;
+    }
+    // the intrinsic size of an image, so the browser can lay out the page
+    // before it has loaded
+    pub fn set_size(mut self, width: u32, height: u32) -> Self {
+        self.width = Some(width);
+        self.height = Some(height);
+        return self;
+    }
     pub fn render<W:Write>(self,indent: usize, f: &mut W) {
         match self.element_type {
             HtmlElementType::P => {
@@ -112,7 +147,7 @@ impl HtmlElement {
                     space_pad(indent)).unwrap();
             }, 
             HtmlElementType::Ul => {
-                write!(f, "{}<ul class={}>\n", 
+                writeln!(f, "{}<ul class={}>", 
                     space_pad(indent), 
                     to_class_string(self.class)).unwrap();
                 for element in self.sub_elements {
@@ -124,36 +159,69 @@ impl HtmlElement {
                 writeln!(f,"{}<li class={}>", 
                     space_pad(indent), 
                     to_class_string(self.class)).unwrap();
+                if self.text.is_some() {
+                    writeln!(f,"{}{}", space_pad(indent+1), to_content_string(self.text)).unwrap();
+                }
                 for element in self.sub_elements {
                     element.render(indent+1, f);
                 }
                 writeln!(f,"{}</li>", space_pad(indent)).unwrap();
             },
             HtmlElementType::Div => {
-                writeln!(f,"{}<div class={}>", 
+                let attributes : String = self.attributes.into_iter()
+                    .map(|(name, value)| to_optional_attribute(&name, Some(value)))
+                    .collect();
+                writeln!(f,"{}<div class={}{}{}>", 
                     space_pad(indent), 
-                    to_class_string(self.class)).unwrap();
+                    to_class_string(self.class),
+                    to_optional_attribute("style", self.style),
+                    attributes).unwrap();
+                if let Some(html) = self.html {
+                    writeln!(f,"{}", html.trim_end()).unwrap();
+                }
                 for element in self.sub_elements {
                     element.render(indent+1, f);
                 }
                 writeln!(f,"{}</div>", space_pad(indent)).unwrap();
             }, 
             HtmlElementType::A => {
-                writeln!(f,"{}<a class={} href={}>", 
+                let attributes : String = self.attributes.into_iter()
+                    .map(|(name, value)| to_optional_attribute(&name, Some(value)))
+                    .collect();
+                writeln!(f,"{}<a class={} href={}{}{}>", 
                     space_pad(indent), 
                     to_class_string(self.class),
-                    to_property_string(self.href)).unwrap();
+                    to_property_string(self.href),
+                    to_optional_attribute("style", self.style),
+                    attributes).unwrap();
+                if self.text.is_some() {
+                    writeln!(f,"{}{}", space_pad(indent+1), to_content_string(self.text)).unwrap();
+                }
                 for element in self.sub_elements {
                     element.render(indent+1, f);
                 }
                 writeln!(f,"{}</a>", space_pad(indent)).unwrap();
             }, 
             HtmlElementType::Img => {
-                writeln!(f,"{}<img class={} src={} alt={}>", 
+                let attributes : String = self.attributes.into_iter()
+                    .map(|(name, value)| to_optional_attribute(&name, Some(value)))
+                    .collect();
+                writeln!(f,"{}<img class={} src={} alt={}{}{}{}{}>", 
                     space_pad(indent), 
                     to_class_string(self.class),
                     to_property_string(self.src),
-                    to_property_string(self.alt)).unwrap();
+                    to_property_string(self.alt),
+                    to_optional_attribute("width", self.width),
+                    to_optional_attribute("height", self.height),
+                    to_optional_attribute("style", self.style),
+                    attributes).unwrap();
+            },
+            HtmlElementType::Noscript => {
+                writeln!(f,"{}<noscript>", space_pad(indent)).unwrap();
+                for element in self.sub_elements {
+                    element.render(indent+1, f);
+                }
+                writeln!(f,"{}</noscript>", space_pad(indent)).unwrap();
             },
             HtmlElementType::Head => {
                 writeln!(f,"{}<head class={}>", 
@@ -175,16 +243,24 @@ impl HtmlElement {
             }, 
 
             HtmlElementType::Link => {
-                writeln!(f, "{}<link rel={} href={}>", 
+                let attributes : String = self.attributes.into_iter()
+                    .map(|(name, value)| to_optional_attribute(&name, Some(value)))
+                    .collect();
+                writeln!(f, "{}<link rel={} href={}{}>", 
                     space_pad(indent), 
                     to_property_string(self.rel),
-                    to_property_string(self.href)).unwrap();
+                    to_property_string(self.href),
+                    attributes).unwrap();
             },
 
             HtmlElementType::Script => {
-                writeln!(f,"{}<script src={}></script>", 
+                let attributes : String = self.attributes.into_iter()
+                    .map(|(name, value)| to_optional_attribute(&name, Some(value)))
+                    .collect();
+                writeln!(f,"{}<script src={}{}></script>", 
                     space_pad(indent), 
-                    to_property_string(self.src)).unwrap();
+                    to_property_string(self.src),
+                    attributes).unwrap();
             },
             HtmlElementType::Title => {
                 writeln!(f,"{}<title>{}</title>", 
@@ -197,37 +273,51 @@ impl HtmlElement {
 
 fn to_property_string(opt_prop: Option<String>) -> String {
     if let Some(prop) = opt_prop {
-        return format!("\"{}\"",prop);
+        return format!("\"{}\"",escape_html(&prop));
     } else {
         return String::from("\"\"");
     }
 }
 
+// an attribute that is left out entirely when not set
+fn to_optional_attribute<T: std::fmt::Display>(name: &str, opt_prop: Option<T>) -> String {
+    match opt_prop {
+        Some(prop) => format!(" {}=\"{}\"", name, escape_html(&prop.to_string())),
+        None => String::new(),
+    }
+}
+
 fn to_content_string(opt_prop: Option<String>) -> String {
     if let Some(prop) = opt_prop {
-        return format!("{}",prop);
+        return escape_html(&prop);
     } else {
         return String::from("");
     }
 }
 
+// text and attribute values can come from file names and captions, so are
+// escaped before being written out
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&'  => escaped.push_str("&amp;"),
+            '<'  => escaped.push_str("&lt;"),
+            '>'  => escaped.push_str("&gt;"),
+            '"'  => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _    => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 fn space_pad(spaces: usize) -> String {
     String::from_iter((0..spaces).map(|_|"    "))
 }
 
 fn to_class_string(classes: Vec<String>) -> String {
-    let mut class_string = String::from("\"");
-    if classes.len()>0 {
-        let final_class_index = classes.len()-1;
-        for (index,class_name) in classes.iter().enumerate() {
-           class_string += class_name;
-           if index == final_class_index {
-               class_string.push(' ');
-           }
-        }
-    }
-    class_string.push('\"');
-    return class_string;
+    format!("\"{}\"", classes.join(" "))
 }
 
 #[cfg(test)]
@@ -255,8 +345,35 @@ mod test {
                 .add_element(HtmlElement::new(HtmlElementType::Ul)
                     .set_text("images_list"))
         );
+        std::fs::create_dir_all("./test_files_out").unwrap();
         let mut writer = BufWriter::new(
             File::create("./test_files_out/html_test.html").unwrap());
         dom.render(&mut writer);
     }
+
+    #[test]
+    fn renders_optional_attributes() {
+        let mut out = Vec::new();
+        HtmlElement::new(HtmlElementType::Img)
+            .add_class("disp_img")
+            .add_class("wide")
+            .set_src("a.jpg")
+            .set_size(640, 480)
+            .render(0, &mut out);
+        assert_eq!(String::from_utf8(out).unwrap(),
+            "<img class=\"disp_img wide\" src=\"a.jpg\" alt=\"\" width=\"640\" height=\"480\">\n");
+    }
+
+    #[test]
+    fn escapes_text_but_not_html() {
+        let mut out = Vec::new();
+        HtmlElement::new(HtmlElementType::P)
+            .set_text("fish & <chips>")
+            .render(0, &mut out);
+        HtmlElement::new(HtmlElementType::Div)
+            .set_html("<em>as is</em>")
+            .render(0, &mut out);
+        assert_eq!(String::from_utf8(out).unwrap(),
+            "<p class=\"\">fish &amp; &lt;chips&gt;</p>\n<div class=\"\">\n<em>as is</em>\n</div>\n");
+    }
 }
\ No newline at end of file
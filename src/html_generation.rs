@@ -1,17 +1,190 @@
+// explicit returns are this file's style
+#![allow(clippy::needless_return)]
+use super::feed::FEED_FILE_NAME;
+use super::geo::{self,MapPoint};
 use super::html_builder::*;
+use super::metadata::star_string;
+use super::options::BuildOptions;
+use super::search::{root_dir,SEARCH_INDEX_FILE_NAME};
+use super::tags::{Tag,TAGS_DIR_NAME};
+use super::timeline::{self,TIMELINE_DIR_NAME};
+use chrono::{Datelike,NaiveDate};
+use std::collections::BTreeMap;
 use super::util::*;
 use std::iter::FromIterator;
 use std::{
-    fs::File,
-    path::PathBuf,
+    fs::{self,File},
+    path::{Path,PathBuf},
     io::BufWriter,
-    str::FromStr
 };
 
 
-pub fn create_html_index(new_file: &PathBuf, ar: &ActionRecord, resources_path: &PathBuf,local_resources:bool) {
-    
-    let styles_path = if !local_resources {
+// how the images on a page are arranged
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Layout {
+    // equal width tiles
+    Grid,
+    // rows of equal height filling the page width, as on flickr
+    Justified,
+    // columns of uncropped images
+    Masonry,
+}
+
+pub const LAYOUT_NAMES: [&str; 3] = ["grid", "justified", "masonry"];
+
+// the height justified rows aim for before being stretched to fill the page
+const JUSTIFIED_ROW_HEIGHT: f32 = 240.0;
+
+impl Layout {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "grid"      => Some(Layout::Grid),
+            "justified" => Some(Layout::Justified),
+            "masonry"   => Some(Layout::Masonry),
+            _ => None,
+        }
+    }
+
+    pub fn class(&self) -> &'static str {
+        match self {
+            Layout::Grid      => "layout_grid",
+            Layout::Justified => "layout_justified",
+            Layout::Masonry   => "layout_masonry",
+        }
+    }
+}
+
+// the file a page of an album is written to, pages counting from 1
+pub fn page_file_name(page: usize) -> String {
+    if page <= 1 {
+        String::from("index.html")
+    } else {
+        format!("page-{}.html", page)
+    }
+}
+
+pub fn is_page_file_name(name: &str) -> bool {
+    name.strip_prefix("page-")
+        .and_then(|rest| rest.strip_suffix(".html"))
+        .map(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+        .unwrap_or(false)
+}
+
+// writes the pages for one album, `new_file` being its index.html. Images are
+// split over several pages of `page_size` if there are more than that, unless
+// it is 0. The first page of the top album, `is_root`, maps every album and
+// links to the tag and timeline pages, with a cloud of all the `tags`.
+// Returns how many pages were written
+pub fn create_html_index(new_file: &Path, ar: &ActionRecord, is_root: bool, tags: &[Tag], options: &BuildOptions) -> usize {
+    let local_resources = options.make_local;
+    let page_size = options.page_size;
+    let layout = options.layout;
+
+    let photos = ar.get_photos();
+    let pages : Vec<&[PhotoAction]> = if page_size == 0 || photos.len() <= page_size {
+        vec![photos]
+    } else {
+        photos.chunks(page_size).collect()
+    };
+    let page_count = pages.len();
+
+    for (index, page_photos) in pages.into_iter().enumerate() {
+        let page = index+1;
+        let title = if page == 1 {
+            String::from("Photo Album")
+        } else {
+            format!("Photo Album - page {} of {}", page, page_count)
+        };
+
+        let mut dom = HtmlDom::new();
+        dom.add_element(format_head(&title, options, "./cacheDir", &root_dir(ar.get_depth())));
+
+        let mut body = HtmlElement::new(HtmlElementType::Body);
+        // build up the top of the body
+        // --- Description ---, from the album's README.md
+        if let (1, Some(description)) = (page, ar.get_description()) {
+            body = body.add_element(HtmlElement::new(HtmlElementType::Div)
+                .add_class("album_description")
+                .set_html(description));
+        }
+        // --- Map ---, only on the first page
+        if page == 1 && options.map {
+            let points = if is_root { album_map_points(ar) } else { Vec::new() };
+            // the top album shows every album if there are several on the map
+            let points = if points.len() > 1 { points } else { photo_map_points(ar, options.detail_pages) };
+            if !points.is_empty() {
+                body = body.add_element(format_map(&points, options));
+            }
+        }
+        // --- Tags and timeline ---, only on the first page of the top album
+        if page == 1 && is_root {
+            if options.timeline {
+                body = body.add_element(HtmlElement::new(HtmlElementType::Div)
+                    .add_class("detail_nav")
+                    .add_element(HtmlElement::new(HtmlElementType::A)
+                        .add_class("page_link")
+                        .set_href(&format!("cacheDir/{}/index.html", TIMELINE_DIR_NAME))
+                        .set_text("browse by date")));
+            }
+            if !tags.is_empty() {
+                body = body.add_element(format_tag_cloud(tags, &format!("cacheDir/{}", TAGS_DIR_NAME)));
+            }
+        }
+        // --- Sub Folders ---, only on the first page
+        if page == 1 && !ar.get_subdirs().is_empty() {
+            body = body.add_element(HtmlElement::new(HtmlElementType::P)
+                            .set_text("Sub Directories")
+                        );
+            // for each folder, format the dir template and insert it
+            let mut list = HtmlElement::new(HtmlElementType::Div)
+                .add_class("dirs_list");
+            for action_record in ar.get_subdirs().iter() {
+                list = list.add_element(format_dir_template(action_record,local_resources));
+            }
+            body = body.add_element(list);
+            body = body.add_element(HtmlElement::new(HtmlElementType::Br));
+        }
+
+
+        // --- Images ---
+        if !page_photos.is_empty() {
+            body = body.add_element(HtmlElement::new(HtmlElementType::P)
+                .set_text("Images")
+            );
+            if page_count > 1 {
+                body = body.add_element(format_pagination(page, page_count));
+            }
+            // for each image, format the image template and insert it
+            let mut list = HtmlElement::new(HtmlElementType::Div)
+                .add_class("images_list")
+                .add_class(layout.class());
+            for photo_action in page_photos {
+                list = list.add_element(format_image_template(photo_action, layout, options.detail_pages, Path::new("")));
+            }
+            body = body.add_element(list);
+            if page_count > 1 {
+                body = body.add_element(format_pagination(page, page_count));
+            }
+            // build up bottom of body
+            body = body.add_element(HtmlElement::new(HtmlElementType::Br));
+        }
+
+        // render to file
+        // setup writer
+        let page_file = new_file.with_file_name(page_file_name(page));
+        let mut writer = BufWriter::new(File::create(page_file).unwrap());
+        dom.add_element(body);
+        dom.render(&mut writer);
+    }
+    page_count
+}
+
+// the stylesheet and script a page links to. Local copies are kept in the
+// cacheDir at `local_dir`, relative to the page. The search index is at the
+// top of the tree, `root_dir`
+fn format_head(title: &str, options: &BuildOptions, local_dir: &str, root_dir: &str) -> HtmlElement {
+    let resources_path = &options.resources_path;
+    let styles_path = if !options.make_local {
         // resources_path.join("styles.css").canonicalize().unwrap()
         if let Ok(thing) = resources_path.join("styles.css").canonicalize() {
             thing
@@ -19,87 +192,534 @@ pub fn create_html_index(new_file: &PathBuf, ar: &ActionRecord, resources_path:
             panic!("Error looking in\n{:?}\n",resources_path);
         }
     } else {
-        PathBuf::from_str("./cacheDir/styles.css").unwrap()
+        PathBuf::from(local_dir).join("styles.css")
     };
 
-    let script_path = if !local_resources {
+    let script_path = if !options.make_local {
         resources_path.join("main.js").canonicalize().unwrap()
     } else {
-        PathBuf::from_str("./cacheDir/main.js").unwrap()
+        PathBuf::from(local_dir).join("main.js")
     };
 
+    let mut head = HtmlElement::new(HtmlElementType::Head)
+        .add_element(HtmlElement::new(HtmlElementType::Link)
+            .set_rel("stylesheet")
+            .set_href(styles_path.to_str().unwrap()));
+    // lets browsers and feed readers find the feed from any page
+    if options.feed {
+        head = head.add_element(HtmlElement::new(HtmlElementType::Link)
+            .set_rel("alternate")
+            .set_href(&format!("{}/{}", root_dir, FEED_FILE_NAME))
+            .set_attribute("type", "application/atom+xml")
+            .set_attribute("title", "Photo Album"));
+    }
+    head.add_element(HtmlElement::new(HtmlElementType::Script)
+            .set_src(&format!("{}/{}", root_dir, SEARCH_INDEX_FILE_NAME))
+            .set_attribute("data-root", root_dir))
+        .add_element(HtmlElement::new(HtmlElementType::Script)
+            .set_src(script_path.to_str().unwrap()))
+        .add_element(HtmlElement::new(HtmlElementType::Title)
+            .set_text(title))
+}
+
+// the page the single page app runs in, written to the top of the tree. Its
+// script, spa.js, and the stylesheet are copied into the cacheDir beside it so
+// the tree can be served from anywhere. Returns the pages written
+pub fn create_spa_shell(root: &ActionRecord, options: &BuildOptions) -> usize {
+    let resources_path = &options.resources_path;
+    let cache_dir_path = root.get_path().join("cacheDir");
+    fs::copy(resources_path.join("spa.js"), cache_dir_path.join("spa.js")).unwrap();
+    fs::copy(resources_path.join("styles.css"), cache_dir_path.join("styles.css")).unwrap();
+
+    let mut head = HtmlElement::new(HtmlElementType::Head)
+        .add_element(HtmlElement::new(HtmlElementType::Link)
+            .set_rel("stylesheet")
+            .set_href("./cacheDir/styles.css"));
+    if options.feed {
+        head = head.add_element(HtmlElement::new(HtmlElementType::Link)
+            .set_rel("alternate")
+            .set_href(FEED_FILE_NAME)
+            .set_attribute("type", "application/atom+xml")
+            .set_attribute("title", "Photo Album"));
+    }
     let mut dom = HtmlDom::new();
-        dom.add_element(
-            HtmlElement::new(HtmlElementType::Head)
-                .add_element(HtmlElement::new(HtmlElementType::Link)
-                    .set_rel("stylesheet")
-                    .set_href(styles_path.to_str().unwrap()))
-                .add_element(HtmlElement::new(HtmlElementType::Script)
-                    .set_src(script_path.to_str().unwrap()))
-                .add_element(HtmlElement::new(HtmlElementType::Title)
-                    .set_text("Photo Album")));
-
-
-
-    let mut body = HtmlElement::new(HtmlElementType::Body);
-    // build up the top of the body
-    // --- Sub Folders ---
-    if ar.get_subdirs().len() > 0 {
-        body = body.add_element(HtmlElement::new(HtmlElementType::P)
-                        .set_text("Sub Directories")
-                    );
-        // for each folder, format the dir template and insert it
-        let mut list = HtmlElement::new(HtmlElementType::Div)
-            .add_class("dirs_list");
-        for action_record in ar.get_subdirs().iter() {
-            // if let Some(element) = format_dir_template(action_record) {
-            //     list = list.add_element(element);
-            // }
-            list = list.add_element(format_dir_template(action_record,local_resources));
+    dom.add_element(head
+        .add_element(HtmlElement::new(HtmlElementType::Script)
+            .set_src("./cacheDir/spa.js"))
+        .add_element(HtmlElement::new(HtmlElementType::Title)
+            .set_text("Photo Album")));
+    dom.add_element(HtmlElement::new(HtmlElementType::Body)
+        .add_element(HtmlElement::new(HtmlElementType::Div)
+            .add_class("spa_album")
+            .set_attribute("data-layout", options.layout.class())));
+    let mut writer = BufWriter::new(File::create(root.get_path().join("index.html")).unwrap());
+    dom.render(&mut writer);
+    1
+}
+
+// where a photo's detail page is written, relative to its album
+pub fn detail_page_path(pa: &PhotoAction) -> PathBuf {
+    let cache_path = get_cache_dir_path(&pa.get_actual(), "cacheDir");
+    let file_name = format!("{}.html", cache_path.file_name().unwrap().to_string_lossy());
+    cache_path.with_file_name(file_name)
+}
+
+// removes the detail pages of the last build from the album's cacheDir, for
+// when this one has none
+pub fn clear_detail_pages(ar: &ActionRecord) {
+    let entries = match fs::read_dir(ar.get_path().join("cacheDir")) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for path in entries.filter_map(|entry_res| entry_res.ok()).map(|entry| entry.path()) {
+        if path.is_file() && is_html_file(&path) {
+            fs::remove_file(&path).unwrap();
         }
-        body = body.add_element(list);
-        body = body.add_element(HtmlElement::new(HtmlElementType::Br));
     }
+}
+
+// writes a page for every photo in the album, with a larger copy, its details
+// and links to the photos either side and the album page it is on. Returns how
+// many were written
+pub fn create_detail_pages(ar: &ActionRecord, options: &BuildOptions) -> usize {
+    let photos = ar.get_photos();
+    // links are relative to cacheDir, where the pages live
+    let page_name = |pa: &PhotoAction| detail_page_path(pa).file_name().unwrap().to_string_lossy().into_owned();
+    for (index, pa) in photos.iter().enumerate() {
+        let image_name = pa.get_name().unwrap();
+        let metadata = pa.get_metadata();
+        let caption = pa.get_caption();
+
+        let mut dom = HtmlDom::new();
+        dom.add_element(format_head(caption.unwrap_or(image_name), options, ".", &root_dir(ar.get_depth()+1)));
+
+        let album_page = match options.page_size {
+            0 => 1,
+            page_size => index/page_size + 1,
+        };
+        let mut nav = HtmlElement::new(HtmlElementType::Div)
+            .add_class("detail_nav")
+            .add_element(HtmlElement::new(HtmlElementType::A)
+                .add_class("page_link")
+                .set_href(&format!("../{}", page_file_name(album_page)))
+                .set_text("up"));
+        if index > 0 {
+            nav = nav.add_element(HtmlElement::new(HtmlElementType::A)
+                .add_class("page_link")
+                .set_href(&page_name(&photos[index-1]))
+                .set_text("previous"));
+        }
+        if index+1 < photos.len() {
+            nav = nav.add_element(HtmlElement::new(HtmlElementType::A)
+                .add_class("page_link")
+                .set_href(&page_name(&photos[index+1]))
+                .set_text("next"));
+        }
+
+        // videos and the like have no web sized copy, so show their thumbnail
+        let shown = pa.get_web_rendition().cloned().unwrap_or_else(|| pa.get_downsized());
+        let original_href = format!("../{}", image_name);
+        let mut body = HtmlElement::new(HtmlElementType::Body)
+            .add_element(nav)
+            .add_element(HtmlElement::new(HtmlElementType::P)
+                .add_class("detail_title")
+                .set_text(pa.get_tags().title.as_deref().unwrap_or(image_name)))
+            .add_element(HtmlElement::new(HtmlElementType::Div)
+                .add_class("detail_image")
+                .add_element(HtmlElement::new(HtmlElementType::A)
+                    .set_href(&original_href)
+                    .add_element(HtmlElement::new(HtmlElementType::Img)
+                        .add_class("detail_img")
+                        .set_src(&shown.file_name().unwrap().to_string_lossy())
+                        .set_alt(caption.unwrap_or(image_name)))));
+        if let Some(caption) = caption {
+            body = body.add_element(HtmlElement::new(HtmlElementType::P)
+                .add_class("detail_caption")
+                .set_text(caption));
+        }
+        let mut details = pa.get_tags().summary();
+        if let Some(metadata) = metadata {
+            details.extend(metadata.summary().into_iter().map(|(label, value)| (label, value.to_owned())));
+        }
+        if let Some((width, height)) = pa.get_dimensions() {
+            details.push(("Dimensions", format!("{} × {}", width, height)));
+        }
+        if !details.is_empty() {
+            let mut list = HtmlElement::new(HtmlElementType::Ul)
+                .add_class("detail_metadata");
+            for (label, value) in details {
+                list = list.add_element(HtmlElement::new(HtmlElementType::Li)
+                    .set_text(&format!("{}: {}", label, value)));
+            }
+            body = body.add_element(list);
+        }
+        body = body.add_element(HtmlElement::new(HtmlElementType::A)
+            .add_class("download_link")
+            .set_href(&original_href)
+            .set_attribute("download", image_name)
+            .set_text("download original"));
+        if let Some(raw_companion) = pa.get_raw_companion() {
+            body = body.add_element(HtmlElement::new(HtmlElementType::A)
+                .add_class("download_link")
+                .set_href(&format!("../{}", raw_companion.to_str().unwrap()))
+                .set_attribute("download", raw_companion.to_str().unwrap())
+                .set_text("download RAW"));
+        }
 
+        let page_file = pa.get_dir().join(detail_page_path(pa));
+        let mut writer = BufWriter::new(File::create(page_file).unwrap());
+        dom.add_element(body);
+        dom.render(&mut writer);
+    }
+    photos.len()
+}
+
+fn tags_dir(root: &ActionRecord) -> PathBuf {
+    root.get_path().join("cacheDir").join(TAGS_DIR_NAME)
+}
+
+// removes the tag pages of the last build
+pub fn clear_tag_pages(root: &ActionRecord) {
+    let tags_dir = tags_dir(root);
+    if tags_dir.exists() {
+        fs::remove_dir_all(&tags_dir).unwrap();
+    }
+}
+
+// writes a page for each tag into the tags directory in the top cacheDir,
+// showing every photo with that tag. The pages of the last build are cleared
+// first, so tags that are no longer used go. Returns how many were written
+pub fn create_tag_pages(root: &ActionRecord, tags: &[Tag], options: &BuildOptions) -> usize {
+    let tags_dir = tags_dir(root);
+    clear_tag_pages(root);
+    if tags.is_empty() {
+        return 0;
+    }
+    fs::create_dir_all(&tags_dir).unwrap();
+    for tag in tags {
+        let mut dom = HtmlDom::new();
+        dom.add_element(format_head(&format!("Photo Album - {}", tag.name), options, "..", "../.."));
 
-    // --- Images ---
-    if ar.get_photos().len() > 0 {
-        body = body.add_element(HtmlElement::new(HtmlElementType::P)
-            .set_text("Images")
-        );
-        // for each image, format the image template and insert it
         let mut list = HtmlElement::new(HtmlElementType::Div)
-            .add_class("images_list");
-        for photo_action in ar.get_photos() {
-            list = list.add_element(format_image_template(photo_action));
+            .add_class("images_list")
+            .add_class(options.layout.class());
+        for pa in tag.photos.iter() {
+            list = list.add_element(format_image_template(pa, options.layout, options.detail_pages, &Path::new("../..").join(relative(&root.get_path(), &pa.get_dir()))));
+        }
+        let body = HtmlElement::new(HtmlElementType::Body)
+            .add_element(HtmlElement::new(HtmlElementType::Div)
+                .add_class("detail_nav")
+                .add_element(HtmlElement::new(HtmlElementType::A)
+                    .add_class("page_link")
+                    .set_href("../../index.html")
+                    .set_text("up")))
+            .add_element(HtmlElement::new(HtmlElementType::P)
+                .set_text(&format!("Tagged {}", tag.name)))
+            .add_element(format_tag_cloud(tags, "."))
+            .add_element(list)
+            .add_element(HtmlElement::new(HtmlElementType::Br));
+
+        let mut writer = BufWriter::new(File::create(tags_dir.join(tag.file_name())).unwrap());
+        dom.add_element(body);
+        dom.render(&mut writer);
+    }
+    tags.len()
+}
+
+// a map of `points`, drawn over tiles by main.js if there is a tile server,
+// and as a static svg otherwise. Tiles are credited beneath the map
+fn format_map(points: &[MapPoint], options: &BuildOptions) -> HtmlElement {
+    let map = HtmlElement::new(HtmlElementType::Div)
+        .add_class("photo_map")
+        .set_attribute("data-tiles", &options.map_tiles)
+        .set_attribute("data-points", &serde_json::to_string(points).unwrap())
+        .set_html(&geo::map_svg(points));
+    match &options.map_attribution {
+        Some(attribution) if !options.map_tiles.is_empty() => HtmlElement::new(HtmlElementType::Div)
+            .add_element(map)
+            .add_element(HtmlElement::new(HtmlElementType::P)
+                .add_class("map_attribution")
+                .set_text(attribution)),
+        _ => map,
+    }
+}
+
+// the located photos of an album, linking to where their thumbnails would
+fn photo_map_points(ar: &ActionRecord, detail_pages: bool) -> Vec<MapPoint> {
+    ar.get_photos().iter()
+        .filter_map(|pa| geo::photo_location(pa).map(|(lat, lon)| {
+            let href = if detail_pages { detail_page_path(pa) } else { pa.get_actual() };
+            MapPoint {
+                lat,
+                lon,
+                label:     pa.get_caption().or_else(|| pa.get_name()).unwrap_or_default().to_owned(),
+                href:      href.to_string_lossy().into_owned(),
+                thumbnail: pa.get_downsized().to_string_lossy().into_owned(),
+            }
+        }))
+        .collect()
+}
+
+// every album in the tree below `root` with located photos, for its top page
+fn album_map_points(root: &ActionRecord) -> Vec<MapPoint> {
+    let root_path = root.get_path();
+    root.get_all_records().into_iter()
+        .filter_map(|ar| {
+            let path = ar.get_path();
+            let album = PathBuf::from(relative(&root_path, &path));
+            let label = match album.file_name() {
+                Some(name) => name.to_string_lossy().into_owned(),
+                None => String::from("Photo Album"),
+            };
+            geo::album_point(ar, &album.join("index.html"), &label, &album)
+        })
+        .collect()
+}
+
+fn timeline_dir(root: &ActionRecord) -> PathBuf {
+    root.get_path().join("cacheDir").join(TIMELINE_DIR_NAME)
+}
+
+// removes the timeline of the last build, whether or not this one has one
+pub fn clear_timeline_pages(root: &ActionRecord) {
+    let timeline_dir = timeline_dir(root);
+    if timeline_dir.exists() {
+        fs::remove_dir_all(&timeline_dir).unwrap();
+    }
+}
+
+// writes the timeline into a directory in the top cacheDir: a page listing the
+// years, then a page for each year, month and day, the days showing their
+// photos. Returns how many pages were written
+pub fn create_timeline_pages(root: &ActionRecord, days: &BTreeMap<NaiveDate, Vec<&PhotoAction>>, options: &BuildOptions) -> usize {
+    let timeline_dir = timeline_dir(root);
+    fs::create_dir_all(&timeline_dir).unwrap();
+    let mut years : BTreeMap<i32, BTreeMap<u32, Vec<NaiveDate>>> = BTreeMap::new();
+    for day in days.keys() {
+        years.entry(day.year()).or_default()
+            .entry(day.month()).or_default()
+            .push(*day);
+    }
+    let timeline_link = (String::from("Timeline"), String::from("index.html"));
+    let mut page_count = 0;
+
+    let mut year_list = HtmlElement::new(HtmlElementType::Div)
+        .add_class("dirs_list");
+    for (year, months) in years.iter() {
+        let year_photos : Vec<&PhotoAction> = months.values().flatten().flat_map(|day| days[day].iter().copied()).collect();
+        year_list = year_list.add_element(format_timeline_tile(root, &timeline::year_file_name(*year), &year.to_string(), &year_photos));
+
+        let year_link = (year.to_string(), timeline::year_file_name(*year));
+        let mut month_list = HtmlElement::new(HtmlElementType::Div)
+            .add_class("dirs_list");
+        for (month, month_days) in months.iter() {
+            let month_label = format!("{} {}", timeline::month_name(*month), year);
+            let month_photos : Vec<&PhotoAction> = month_days.iter().flat_map(|day| days[day].iter().copied()).collect();
+            month_list = month_list.add_element(format_timeline_tile(root, &timeline::month_file_name(*year, *month), &month_label, &month_photos));
+
+            let month_link = (month_label.clone(), timeline::month_file_name(*year, *month));
+            let mut day_list = HtmlElement::new(HtmlElementType::Div)
+                .add_class("dirs_list");
+            for day in month_days.iter() {
+                let photos = &days[day];
+                let day_label = format!("{} {}", day.day(), month_label);
+                day_list = day_list.add_element(format_timeline_tile(root, &timeline::day_file_name(day), &day_label, photos));
+
+                let mut images = HtmlElement::new(HtmlElementType::Div)
+                    .add_class("images_list")
+                    .add_class(options.layout.class());
+                for pa in photos.iter() {
+                    images = images.add_element(format_image_template(pa, options.layout, options.detail_pages, &Path::new("../..").join(relative(&root.get_path(), &pa.get_dir()))));
+                }
+                let crumbs = [timeline_link.clone(), year_link.clone(), month_link.clone()];
+                write_timeline_page(&timeline_dir.join(timeline::day_file_name(day)), &day_label, &crumbs, images, options);
+                page_count += 1;
+            }
+            write_timeline_page(&timeline_dir.join(timeline::month_file_name(*year, *month)), &month_label, &[timeline_link.clone(), year_link.clone()], day_list, options);
+            page_count += 1;
         }
-        body = body.add_element(list);
-        // build up bottom of body
-        body = body.add_element(HtmlElement::new(HtmlElementType::Br));
+        write_timeline_page(&timeline_dir.join(timeline::year_file_name(*year)), &year.to_string(), std::slice::from_ref(&timeline_link), month_list, options);
+        page_count += 1;
     }
+    let crumbs = [(String::from("Photo Album"), String::from("../../index.html"))];
+    write_timeline_page(&timeline_dir.join("index.html"), "Timeline", &crumbs, year_list, options);
+    page_count + 1
+}
 
-    // render to file
-    // setup writer
-    let mut writer = BufWriter::new(File::create(new_file).unwrap());
+// a year, month or day of the timeline, shown by its first photo
+fn format_timeline_tile(root: &ActionRecord, href: &str, label: &str, photos: &[&PhotoAction]) -> HtmlElement {
+    let mut link = HtmlElement::new(HtmlElementType::A)
+        .add_class("dirs_link")
+        .set_href(href);
+    if let Some(cover) = photos.first() {
+        link = link.add_element(HtmlElement::new(HtmlElementType::Img)
+            .add_class("dirs_img")
+            .set_src(&format!("../../{}", relative(&root.get_path(), &cover.get_abs_downsized())))
+            .set_alt(label)
+            .set_attribute("loading", "lazy"));
+    }
+    HtmlElement::new(HtmlElementType::Div)
+        .add_class("dirs_item")
+        .add_element(link
+            .add_element(HtmlElement::new(HtmlElementType::P)
+                .set_text(&format!("{} ({})", label, photos.len()))))
+}
+
+// `crumbs` are the label and file of each level above this page
+fn write_timeline_page(page_file: &Path, title: &str, crumbs: &[(String, String)], content: HtmlElement, options: &BuildOptions) {
+    let mut dom = HtmlDom::new();
+    dom.add_element(format_head(&format!("Photo Album - {}", title), options, "..", "../.."));
+    let mut nav = HtmlElement::new(HtmlElementType::Div)
+        .add_class("detail_nav");
+    for (label, href) in crumbs {
+        nav = nav.add_element(HtmlElement::new(HtmlElementType::A)
+            .add_class("page_link")
+            .set_href(href)
+            .set_text(label));
+    }
+    let body = HtmlElement::new(HtmlElementType::Body)
+        .add_element(nav)
+        .add_element(HtmlElement::new(HtmlElementType::P)
+            .set_text(title))
+        .add_element(content)
+        .add_element(HtmlElement::new(HtmlElementType::Br));
+    let mut writer = BufWriter::new(File::create(page_file).unwrap());
     dom.add_element(body);
     dom.render(&mut writer);
 }
 
-fn format_image_template(pa: &PhotoAction) -> HtmlElement {
-    let downsizes_image_location = pa.get_downsized();
-    let image_name = downsizes_image_location.file_name().unwrap().to_str().unwrap();
-    let he = HtmlElement::new(HtmlElementType::Div)
-        .add_class("images_item")
-        .add_element(HtmlElement::new(HtmlElementType::A)
-            .add_class("images_link")
-            .set_href(pa.get_actual().to_str().unwrap())
-            .add_element(HtmlElement::new(HtmlElementType::Img)
-                .set_src(pa.get_downsized().to_str().unwrap())
-                .set_alt(image_name))
-                .add_class("disp_img"));
+// a link to every tag's page in `tags_dir`, bigger for tags with more photos
+fn format_tag_cloud(tags: &[Tag], tags_dir: &str) -> HtmlElement {
+    let most = tags.iter().map(|tag| tag.photos.len()).max().unwrap_or(1) as f32;
+    let mut cloud = HtmlElement::new(HtmlElementType::Div)
+        .add_class("tag_cloud");
+    for tag in tags {
+        let size = 0.9 + (tag.photos.len() as f32/most)*1.1;
+        cloud = cloud.add_element(HtmlElement::new(HtmlElementType::A)
+            .add_class("tag_link")
+            .set_href(&format!("{}/{}", tags_dir, tag.file_name()))
+            .set_style(&format!("font-size: {:.2}em", size))
+            .set_text(&tag.name));
+    }
+    cloud
+}
+
+// previous and next links with a link to every page between
+fn format_pagination(page: usize, page_count: usize) -> HtmlElement {
+    let mut controls = HtmlElement::new(HtmlElementType::Div)
+        .add_class("pagination");
+    if page > 1 {
+        controls = controls.add_element(HtmlElement::new(HtmlElementType::A)
+            .add_class("page_link")
+            .set_href(&page_file_name(page-1))
+            .set_text("previous"));
+    }
+    for other_page in 1..=page_count {
+        let mut link = HtmlElement::new(HtmlElementType::A)
+            .add_class("page_link")
+            .set_href(&page_file_name(other_page))
+            .set_text(&other_page.to_string());
+        if other_page == page {
+            link = link.add_class("current_page");
+        }
+        controls = controls.add_element(link);
+    }
+    if page < page_count {
+        controls = controls.add_element(HtmlElement::new(HtmlElementType::A)
+            .add_class("page_link")
+            .set_href(&page_file_name(page+1))
+            .set_text("next"));
+    }
+    controls
+}
+
+// thumbnails link to the photo's detail page if there is one, or the original.
+// Paths are relative to `base`, the photo's album as seen from the page
+fn format_image_template(pa: &PhotoAction, layout: Layout, detail_pages: bool, base: &Path) -> HtmlElement {
+    let image_name = pa.get_name().unwrap();
+    let thumbnail_path = base.join(pa.get_downsized());
+    let thumbnail_src = thumbnail_path.to_str().unwrap();
+    let size = pa.get_thumbnail_size();
+    let thumbnail_img = |src: &str| {
+        let img = HtmlElement::new(HtmlElementType::Img)
+            .add_class("disp_img")
+            .set_src(src)
+            .set_alt(image_name)
+            .set_attribute("loading", "lazy")
+            .set_attribute("decoding", "async");
+        match size {
+            Some((width, height)) => img.set_size(width, height),
+            None => img,
+        }
+    };
+
+    let href = base.join(if detail_pages { detail_page_path(pa) } else { pa.get_actual() });
+    let mut link = HtmlElement::new(HtmlElementType::A)
+        .add_class("images_link")
+        .set_href(href.to_str().unwrap());
+    match pa.get_placeholder() {
+        // main.js swaps the placeholder for the thumbnail as it scrolls into
+        // view, the noscript copy covers browsers without javascript
+        Some(placeholder) => {
+            let [red, green, blue] = placeholder.colour;
+            link = link
+                .add_element(thumbnail_img(&placeholder.data_uri)
+                    .add_class("lazy_img")
+                    .set_attribute("data-src", thumbnail_src)
+                    .set_style(&format!("background-color: #{:02x}{:02x}{:02x}", red, green, blue)))
+                .add_element(HtmlElement::new(HtmlElementType::Noscript)
+                    .add_element(thumbnail_img(thumbnail_src)));
+        },
+        None => link = link.add_element(thumbnail_img(thumbnail_src)),
+    }
+
+    let tags = pa.get_tags();
+    let mut he = HtmlElement::new(HtmlElementType::Div)
+        .add_class("images_item");
+    // colour labels outline the item
+    if let Some(label) = &tags.label {
+        he = he.add_class(&label_class(label));
+    }
+    // justified rows share out the page width in proportion to each image's
+    // aspect ratio
+    if let (Layout::Justified, Some((width, height))) = (layout, size) {
+        let aspect_ratio = width as f32/height.max(1) as f32;
+        he = he.set_style(&format!("flex-grow: {:.0}; flex-basis: {:.0}px",
+            aspect_ratio*100.0, aspect_ratio*JUSTIFIED_ROW_HEIGHT));
+    }
+    he = he.add_element(link);
+    if let Some(title) = &tags.title {
+        he = he.add_element(HtmlElement::new(HtmlElementType::P)
+            .add_class("image_title")
+            .set_text(title));
+    }
+    if tags.stars() > 0 {
+        he = he.add_element(HtmlElement::new(HtmlElementType::P)
+            .add_class("image_rating")
+            .set_text(&star_string(tags.stars())));
+    }
+    if let Some(caption) = pa.get_caption() {
+        he = he.add_element(HtmlElement::new(HtmlElementType::P)
+            .add_class("image_caption")
+            .set_text(caption));
+    }
+    // raw+jpeg pairs link to the raw file as well
+    if let Some(raw_companion) = pa.get_raw_companion() {
+        he = he.add_element(HtmlElement::new(HtmlElementType::A)
+            .add_class("raw_link")
+            .set_href(base.join(raw_companion).to_str().unwrap())
+            .set_text("RAW"));
+    }
     return he;
 }
 
+fn label_class(label: &str) -> String {
+    let name : String = label.to_lowercase().chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("label_{}", name)
+}
+
 fn format_dir_template(ar: &ActionRecord,local_resources:bool) -> HtmlElement {
 
     let mut sub_folder_index_path = ar.get_path();
@@ -173,10 +793,10 @@ fn format_dir_template(ar: &ActionRecord,local_resources:bool) -> HtmlElement {
 
 }
 
-fn get_first_photo(ar: &ActionRecord) -> Option<PhotoAction> {
-    if ar.get_photos().len() > 0 {
+pub fn get_first_photo(ar: &ActionRecord) -> Option<PhotoAction> {
+    if !ar.get_photos().is_empty() {
         return Some(ar.get_photos()[0].clone())
-    } else if ar.get_subdirs().len() > 0 {
+    } else if !ar.get_subdirs().is_empty() {
         for i in 0..ar.get_subdirs().len() {
             if let Some(pa) = get_first_photo(&ar.get_subdirs()[i]) {
                 return Some(pa)
@@ -187,3 +807,41 @@ fn get_first_photo(ar: &ActionRecord) -> Option<PhotoAction> {
         return None;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn names_pages() {
+        assert_eq!(page_file_name(1), "index.html");
+        assert_eq!(page_file_name(3), "page-3.html");
+        assert!(is_page_file_name("page-12.html"));
+        assert!(!is_page_file_name("page-.html"));
+        assert!(!is_page_file_name("page-two.html"));
+        assert!(!is_page_file_name("index.html"));
+    }
+
+    #[test]
+    fn links_detail_pages() {
+        let root = PathBuf::from("./test_files_out/detail_pages");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("cacheDir")).unwrap();
+        let mut ar = ActionRecord::new(&root);
+        for name in ["a.jpg", "b.jpg", "c.jpg"].iter() {
+            let pa = PhotoAction::new(root.clone(), PathBuf::from(name), PathBuf::from(format!("cacheDir/{}.500w.jpg", name)), crate::media::MediaKind::Image);
+            ar.add_photo_action(pa);
+        }
+        let mut options = BuildOptions::new(PathBuf::from("./resources"));
+        options.make_local = true;
+        options.page_size = 2;
+        assert_eq!(create_detail_pages(&ar, &options), 3);
+
+        let page = std::fs::read_to_string(root.join("cacheDir").join("c.jpg.html")).unwrap();
+        assert!(page.contains("href=\"../page-2.html\""));
+        assert!(page.contains("href=\"b.jpg.html\""));
+        assert!(!page.contains("next"));
+        assert!(page.contains("href=\"../c.jpg\" download=\"c.jpg\""));
+        assert!(page.contains("href=\"./styles.css\""));
+    }
+}
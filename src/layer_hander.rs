@@ -1,35 +1,121 @@
 use std::{
+    collections::HashMap,
     fs,
-    io,
     fs::DirEntry,
-    path::PathBuf,
+    path::{Path,PathBuf},
 };
 
 use crate::ffmpeg_interface;
 use rayon::prelude::*;
 use super::util;
-use super::util::{ActionRecord,PhotoAction,is_html_file,is_image_file,get_cache_dir_path};
+use super::util::{ActionRecord,PhotoAction,is_html_file};
+use super::thumbnail::{self,ThumbnailInfo,ThumbnailInfoCache,ThumbnailSettings};
+use super::album_config::AlbumConfig;
+use super::captions::{self,DirCaptions};
+use super::feed;
+use super::formats::{self,PhotoFormat};
+use super::geo;
+use super::manifest;
+use super::media::{self,MediaKind};
+use super::metadata::{self,PhotoTags};
+use log::{debug,warn};
 use super::html_generation::*;
+use super::filters::Filters;
+use super::options::{BuildOptions,OutputTarget};
+use super::progress::{Progress,RenderOutcome};
+use super::search::{SearchIndex,clear_search_index,search_index_path};
+use super::tags::{self,Tag};
+use super::timeline;
+use super::traversal::{EntryKind,Traversal};
 
-// pub fn handle_layer(path: &PathBuf, current_depth: usize, max_depth: usize,clean: bool,resources_path: &PathBuf, downsize_image_width: u32) -> Option<ActionRecord> {
-pub fn handle_layer(path: &PathBuf, current_depth: usize, max_depth: usize,clean: bool,resources_path: &PathBuf,make_local:bool, force_regen: bool, use_ffmpeg:bool) -> Option<ActionRecord> {
-    println!("starting work in {:?}",path);
-    if current_depth == max_depth+1 {
+// builds the album in three phases: the whole tree is scanned into ActionRecords
+// first, then every thumbnail in the tree is rendered as one parallel work queue,
+// and finally the index pages are written. Must be called from within the rayon
+// pool that should do the work.
+pub fn handle_layer(path: &PathBuf, options: &BuildOptions, progress: &Progress) -> Option<ActionRecord> {
+    let mut traversal = Traversal::new(&options.traversal, path);
+    let mut filters = match Filters::new(path, &options.include_globs, &options.exclude_globs) {
+        Ok(filters) => filters,
+        Err(reason) => panic!("did not understand glob: {}", reason),
+    };
+    let action_record = scan_layer(path, 0, options, &options.thumbnails, &[], &mut traversal, &mut filters)?;
+    progress.set_discovered(action_record.get_all_photos().len());
+    process_photos(&action_record, options.force_regen, options.use_ffmpeg, progress);
+    let pages = match options.output {
+        OutputTarget::Html => {
+            let tags = tags::collect_tags(&action_record);
+            let mut pages = write_pages(&action_record, &tags, options);
+            if !options.detail_pages {
+                action_record.get_all_records().iter().for_each(|ar| clear_detail_pages(ar));
+            }
+            pages += create_tag_pages(&action_record, &tags, options);
+            clear_timeline_pages(&action_record);
+            if options.timeline {
+                pages += create_timeline_pages(&action_record, &timeline::collect_days(&action_record), options);
+            }
+            if let Err(reason) = SearchIndex::new(&action_record, options.detail_pages).write(&action_record) {
+                progress.record_write_failure(&search_index_path(&action_record), &reason);
+            }
+            pages
+        },
+        // the app draws every album from its manifest. The scan has already
+        // removed the album pages, the other pages of an html build go too
+        OutputTarget::Spa => {
+            action_record.get_all_records().iter().for_each(|ar| clear_detail_pages(ar));
+            clear_tag_pages(&action_record);
+            clear_timeline_pages(&action_record);
+            clear_search_index(&action_record);
+            let manifests = manifest::write_manifests(&action_record, progress);
+            debug!("{} album manifests written", manifests);
+            create_spa_shell(&action_record, options)
+        },
+    };
+    if options.feed {
+        match feed::write_feed(&action_record, options) {
+            Ok(albums) => debug!("{} albums in the feed", albums),
+            Err(reason) => progress.record_write_failure(&feed::feed_path(&action_record), &reason),
+        }
+    } else {
+        feed::clear_feed(&action_record);
+    }
+    if options.map {
+        match geo::write_geojson(&action_record) {
+            Ok(located) => debug!("{} photos have a location", located),
+            Err(reason) => progress.record_write_failure(&geo::geojson_path(&action_record), &reason),
+        }
+    } else {
+        geo::clear_geojson(&action_record);
+    }
+    progress.add_pages(pages);
+    Some(action_record)
+}
+
+// phase 1: walk the directory tree, preparing cache dirs and recording every
+// photo that needs a thumbnail. No pixels are decoded here, but every file's
+// first bytes are read to identify it, gifs are walked to see if they are
+// animated and photos are searched for their tags. `thumbnails` and
+// `album_tags` are the settings and tags inherited from the parent directory
+pub fn scan_layer(path: &PathBuf, current_depth: usize, options: &BuildOptions, thumbnails: &ThumbnailSettings, album_tags: &[String], traversal: &mut Traversal, filters: &mut Filters) -> Option<ActionRecord> {
+    debug!("scanning {:?}",path);
+    if current_depth == options.max_depth+1 || !traversal.enter_dir(path) {
         return None;
     }
     let mut action_record = ActionRecord::new(path);
+    action_record.set_depth(current_depth);
+    action_record.read_modified();
+    // an album.toml changes how this directory and those below it are thumbnailed
+    let (thumbnails, album_tags) = match AlbumConfig::load(path) {
+        Some(config) => (config.apply(thumbnails), config.apply_tags(album_tags)),
+        None => (*thumbnails, album_tags.to_vec()),
+    };
+    action_record.set_thumbnail_settings(thumbnails);
+    action_record.set_description(captions::album_description(path));
+    let dir_captions = DirCaptions::load(path);
     // look for existing cache dir
     let cache_dir_path = path.join("cacheDir");
-    if clean {
-        if cache_dir_path.exists() {
-            // delete it
-            fs::remove_dir_all(cache_dir_path.clone()).unwrap();
-        }
-        // make cache dir
-        fs::create_dir(cache_dir_path.clone()).unwrap();
-    } else if !cache_dir_path.exists() {
+    if !cache_dir_path.exists() {
         // make cache dir
-        fs::create_dir(cache_dir_path.clone()).unwrap();
+        fs::create_dir(cache_dir_path).unwrap();
     }
 
     // handle previous versions of this program
@@ -38,82 +124,76 @@ pub fn handle_layer(path: &PathBuf, current_depth: usize, max_depth: usize,clean
         fs::remove_dir_all(prev_cache_dir_name).unwrap();
     }
 
-    // first isolate all the directories and files in this dir
-    let entries : Vec<Result<fs::DirEntry, io::Error>> = fs::read_dir(path).unwrap().collect();
-    let mut directories : Vec<&DirEntry> = entries.iter().filter_map(|entry_res|
-        if let Ok(d_entry) = entry_res {
-            if d_entry.file_type().unwrap().is_dir() {
-                Some(d_entry)
-            } else {
-                None
-            }
-        } else {
-            None
-        }
-    ).collect();
-    let files : Vec<&DirEntry> = entries.iter().filter_map(|entry_res|
-        if let Ok(d_entry) = entry_res {
-            if d_entry.file_type().unwrap().is_file() {
-                Some(d_entry)
-            } else {
-                None
-            }
-        } else {
-            None
+    // first isolate all the directories and files in this dir, as allowed by
+    // the traversal policy and any include/exclude filters
+    filters.enter_dir(path);
+    let entries : Vec<DirEntry> = fs::read_dir(path).unwrap()
+        .filter_map(|entry_res| entry_res.ok())
+        .collect();
+    let mut directories : Vec<&DirEntry> = Vec::new();
+    let mut files : Vec<&DirEntry> = Vec::new();
+    for entry in entries.iter() {
+        match traversal.classify(entry) {
+            Some(EntryKind::Dir) if filters.allows(&entry.path(), true) => directories.push(entry),
+            Some(EntryKind::File) if filters.allows(&entry.path(), false) => files.push(entry),
+            _ => {},
         }
-    ).collect();
+    }
 
-    // recursively act on all the directories
+    // recursively scan all the directories
     directories.sort_by(|a,b| {
         a.file_name().to_str().unwrap().to_lowercase().cmp(&b.file_name().to_str().unwrap().to_lowercase())
     });
     for directory in directories {
         let os_str_file_name = directory.file_name();
         let file_name = os_str_file_name.to_str().unwrap();
-        // ignore the cacheDirs we put there, and ignore any hidden folders
-        if file_name!="cacheDir" && file_name.chars().nth(0).unwrap() != '.' {
+        // ignore the cacheDirs we put there
+        if file_name!="cacheDir" {
             // is dir -> recurse
-            // if let Some(action) = handle_layer(&directory.path(),current_depth+1,max_depth,clean,resources_path,downsize_image_width) {
-            if let Some(action) = handle_layer(&directory.path(),current_depth+1,max_depth,clean,resources_path,make_local,force_regen,use_ffmpeg) {
+            if let Some(action) = scan_layer(&directory.path(),current_depth+1,options,&thumbnails,&album_tags,traversal,filters) {
                 action_record.add_subdir_action(action);
             }
         }
     }
+    filters.leave_dir();
 
-
-    // once directories are finished, apply rayon to allow multi-threaded downsampling
-    // to images in this directory
-    let mut pas : Vec<PhotoAction> = files.par_iter().filter_map(|file| {
+    let use_ffmpeg = options.use_ffmpeg;
+    let pas : Vec<PhotoAction> = files.iter().filter_map(|file| {
         // this needs to be canonicalisaed
         let abs_file_path = file.path();
         if !abs_file_path.is_absolute() {
             panic!("this should be abs {:?}",abs_file_path);
         }
 
-        // let containing_dir : PathBuf = if make_local {
-            //     PathBuf::from(".")
-            // } else {
-                //     abs_file_path.parent().unwrap().into()
-                // };
         let containing_dir : PathBuf = abs_file_path.parent().unwrap().into();
         let relative_path  : PathBuf = abs_file_path.file_name().unwrap().into();
 
-        // don't bother checking if its an image file if using ffmpeg, let ffmpeg
-        // decide if it can work on it.
-        if is_image_file(&abs_file_path) || use_ffmpeg {
-            // is photo 
-            let abs_cache_path      = get_cache_dir_path(&abs_file_path, "cacheDir");
-            let relative_cache_path = get_cache_dir_path(&relative_path, "cacheDir");
-            // make record
-            let pa = PhotoAction::new(containing_dir, relative_path,relative_cache_path);
-            // action_record.add_photo_action(pa);
-            // downsize, save in cache dir
-            // downsize_image(&abs_file_path, &abs_cache_path, downsize_image_width);
-            // return Some(pa);
-            if use_ffmpeg {
-                ffmpeg_interface::downsize_image(&abs_file_path, &abs_cache_path, 500,force_regen);
-            } else {
-                util::downsize_image(&abs_file_path, &abs_cache_path, 500,force_regen);
+        let (kind, format) = media::identify(&abs_file_path);
+        // images are decoded locally if this build supports their format, raw
+        // files use their embedded preview, anything else needs ffmpeg
+        let has_backend = if kind == MediaKind::Raw || (use_ffmpeg && ffmpeg_interface::is_compatible_kind(kind)) {
+            true
+        } else if kind == MediaKind::Image {
+            match format {
+                Some(format) if !format.decodable_locally() => {
+                    warn!("{:?} - skipping, {} support: {}", abs_file_path, format.name(), format.capability());
+                    false
+                },
+                _ => true,
+            }
+        } else {
+            false
+        };
+        if has_backend {
+            // is photo -> make record, the thumbnail is rendered later
+            let animated = format == Some(PhotoFormat::Gif) && formats::is_animated_gif(&abs_file_path);
+            let relative_cache_path = thumbnails.thumbnail_path(&relative_path, "cacheDir", kind, animated);
+            let mut pa = PhotoAction::new(containing_dir, relative_path.clone(),relative_cache_path,kind);
+            if options.detail_pages && (kind == MediaKind::Image || kind == MediaKind::Raw) {
+                pa.set_web_rendition(thumbnails.web_rendition().thumbnail_path(&relative_path, "cacheDir", kind, false));
+            }
+            if let Some(caption) = dir_captions.caption_for(&abs_file_path) {
+                pa.set_caption(caption);
             }
             Some(pa)
         } else if is_html_file(&abs_file_path) {
@@ -121,34 +201,182 @@ pub fn handle_layer(path: &PathBuf, current_depth: usize, max_depth: usize,clean
             fs::remove_file(abs_file_path).unwrap();
             None
         } else {
+            debug!("{:?} - skipping {:?} file", abs_file_path, kind);
             None
         }
     }).collect();
+    let mut pas = pair_raw_files(pas);
+    // ratings and keywords are read now, so photos can be filtered on them
+    // before anything is rendered. Only photos carry them
+    pas.par_iter_mut().for_each(|pa| {
+        let mut tags = match pa.get_kind() {
+            MediaKind::Image | MediaKind::Raw => metadata::read_tags(&pa.get_abs_actual()),
+            _ => PhotoTags::default(),
+        };
+        tags.add_keywords(&album_tags);
+        if let Some(description) = &tags.description {
+            pa.set_caption(description.clone());
+        }
+        pa.set_tags(tags);
+    });
+    if let Some(min_rating) = options.min_rating {
+        pas.retain(|pa| {
+            let keep = pa.get_tags().stars() >= min_rating;
+            if !keep {
+                debug!("{:?} - skipping, rated below {}", pa.get_abs_actual(), min_rating);
+            }
+            keep
+        });
+    }
     pas.sort_by(|a, b| {
         a.get_name().unwrap().to_lowercase().cmp(&b.get_name().unwrap().to_lowercase())
-        // a.to_lowercase().cmp(&b.to_lowercase()
     });
     for pa in pas  {
         action_record.add_photo_action(pa);
     }
 
-    // if needed, copy over css and js files to local dir to make files simpler to reference
-    if make_local {
-        // todo check if theres a new version first, but overrisde this with force_regen
-        for file_name in ["main.js", "styles.css"].iter() {
-            fs::copy(resources_path.join(file_name), cache_dir_path.join(file_name)).unwrap();
-            fs::copy(resources_path.join(file_name), cache_dir_path.join(file_name)).unwrap();
+    Some(action_record)
+}
+
+// a raw file with a jpeg (or other image) of the same name beside it becomes
+// part of that image's album item rather than an item of its own
+fn pair_raw_files(pas: Vec<PhotoAction>) -> Vec<PhotoAction> {
+    let stem = |pa: &PhotoAction| pa.get_actual().file_stem().map(|stem| stem.to_string_lossy().to_lowercase());
+    let (raws, mut others) : (Vec<PhotoAction>, Vec<PhotoAction>) = pas.into_iter()
+        .partition(|pa| pa.get_kind() == MediaKind::Raw);
+    let mut unpaired = Vec::new();
+    for raw in raws {
+        let partner = others.iter_mut()
+            .find(|pa| pa.get_kind() == MediaKind::Image && pa.get_raw_companion().is_none() && stem(pa) == stem(&raw));
+        match partner {
+            Some(partner) => {
+                debug!("{:?} - paired with {:?}", raw.get_abs_actual(), partner.get_actual());
+                partner.set_raw_companion(raw.get_actual());
+            },
+            None => unpaired.push(raw),
         }
     }
+    others.extend(unpaired);
+    others
+}
 
-    let mut file_path = path.clone();
-    file_path.push("index.html");
-    create_html_index(&file_path, &action_record,resources_path,make_local);
-    if current_depth==0 {
-        // if this is the first layer, no point returning anything
-        None
-    } else {
-        // if this is not the first layer, return status
-        Some(action_record)
+// phase 2: every photo in the tree is downsized as a single parallel work queue,
+// so the pool stays busy regardless of how the photos are spread over directories
+pub fn process_photos(action_record: &ActionRecord, force_regen: bool, use_ffmpeg:bool, progress: &Progress) {
+    let records = action_record.get_all_records();
+    // what is known of the thumbnails already rendered, by cacheDir
+    let known : HashMap<PathBuf, ThumbnailInfoCache> = records.iter()
+        .map(|record| {
+            let cache_dir = record.get_path().join("cacheDir");
+            let info = ThumbnailInfoCache::load(&cache_dir);
+            (cache_dir, info)
+        })
+        .collect();
+    let photos : Vec<(&PhotoAction, &ThumbnailSettings)> = records.iter()
+        .flat_map(|record| record.get_photos().iter().map(move |pa| (pa, record.get_thumbnail_settings())))
+        .collect();
+    photos.par_iter().for_each(|(pa, settings)| {
+        let abs_file_path  = pa.get_abs_actual();
+        let abs_cache_path = pa.get_abs_downsized();
+        // images are decoded locally where possible, falling back to ffmpeg for
+        // formats the image crate can't read but ffmpeg can. Raw files can't
+        // be handed to it
+        let (outcome, info) = match pa.get_kind() {
+            MediaKind::Image | MediaKind::Raw => {
+                let rendering = util::downsize_image(&abs_file_path, &abs_cache_path, settings,force_regen);
+                if rendering.0 == RenderOutcome::Failed && use_ffmpeg && ffmpeg_interface::is_compatible(&abs_file_path) {
+                    ffmpeg_interface::downsize_image(&abs_file_path, &abs_cache_path, settings, force_regen)
+                } else {
+                    rendering
+                }
+            },
+            _ => ffmpeg_interface::downsize_image(&abs_file_path, &abs_cache_path, settings, force_regen),
+        };
+        // the pages need each thumbnail's size for their layout, and a tiny
+        // placeholder to show while it loads. Up to date thumbnails are only
+        // decoded if they were rendered before their details were kept
+        let info = match outcome {
+            RenderOutcome::UpToDate => known_info(&known, &abs_cache_path)
+                .or_else(|| image::open(&abs_cache_path).ok().map(|thumbnail| thumbnail::describe(&thumbnail))),
+            _ => info,
+        };
+        if let Some(info) = info {
+            pa.set_thumbnail_size(info.size.0, info.size.1);
+            if let Some(placeholder) = info.placeholder {
+                pa.set_placeholder(placeholder);
+            }
+        }
+        // only the header is read for the size, raw files are left without
+        if pa.get_kind() == MediaKind::Image {
+            if let Ok((width, height)) = image::image_dimensions(&abs_file_path) {
+                pa.set_dimensions(width, height);
+            }
+        }
+        // the exif description is the last resort for a caption
+        if pa.get_kind() == MediaKind::Image || pa.get_kind() == MediaKind::Raw {
+            if let Some(metadata) = metadata::read_metadata(&abs_file_path) {
+                if let Some(description) = &metadata.description {
+                    pa.set_caption(description.clone());
+                }
+                pa.set_metadata(metadata);
+            }
+        }
+        // detail pages show a larger copy along with the photo's exif details
+        let outcome = match pa.get_web_rendition() {
+            Some(web_rendition) => {
+                let abs_web_path = pa.get_dir().join(web_rendition);
+                outcome.combine(util::downsize_image(&abs_file_path, &abs_web_path, &settings.web_rendition(), force_regen).0)
+            },
+            None => outcome,
+        };
+        progress.record(&abs_file_path, outcome);
+    });
+
+    // rewritten from scratch, so thumbnails that have gone are forgotten
+    for record in records.iter().filter(|record| !record.get_photos().is_empty()) {
+        let mut known = ThumbnailInfoCache::default();
+        for pa in record.get_photos() {
+            if let (Some(size), Some(name)) = (pa.get_thumbnail_size(), pa.get_downsized().file_name()) {
+                known.insert(name.to_string_lossy().into_owned(), ThumbnailInfo { size, placeholder: pa.get_placeholder().cloned() });
+            }
+        }
+        let cache_dir = record.get_path().join("cacheDir");
+        if let Err(reason) = known.save(&cache_dir) {
+            debug!("{:?} - could not keep the thumbnail details: {}", cache_dir, reason);
+        }
     }
-}
\ No newline at end of file
+}
+
+fn known_info(known: &HashMap<PathBuf, ThumbnailInfoCache>, thumbnail_path: &Path) -> Option<ThumbnailInfo> {
+    let cache_dir = thumbnail_path.parent()?;
+    let name = thumbnail_path.file_name()?.to_str()?;
+    known.get(cache_dir)?.get(name).cloned()
+}
+
+// phase 3: write the index pages into every directory of the tree, returning
+// how many were written. The top page gets a cloud of all the `tags`. The tag
+// and timeline pages are written after
+pub fn write_pages(action_record: &ActionRecord, tags: &[Tag], options: &BuildOptions) -> usize {
+    let resources_path = &options.resources_path;
+    let make_local = options.make_local;
+    let records = action_record.get_all_records();
+    records.par_iter().map(|ar| {
+        let path = ar.get_path();
+        // if needed, copy over css and js files to local dir to make files simpler to reference
+        if make_local {
+            // todo check if theres a new version first, but overrisde this with force_regen
+            let cache_dir_path = path.join("cacheDir");
+            for file_name in ["main.js", "styles.css"].iter() {
+                fs::copy(resources_path.join(file_name), cache_dir_path.join(file_name)).unwrap();
+            }
+        }
+
+        let file_path = path.join("index.html");
+        let is_root = path == action_record.get_path();
+        let mut pages = create_html_index(&file_path, ar, is_root, tags, options);
+        if options.detail_pages {
+            pages += create_detail_pages(ar, options);
+        }
+        pages
+    }).sum()
+}
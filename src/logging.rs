@@ -0,0 +1,133 @@
+use std::{
+    fs::File,
+    io::{self,IsTerminal,Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use log::{Level,LevelFilter,Log,Metadata,Record};
+
+// kept in the top cacheDir, so it isn't published with the album
+pub const LOG_FILE_NAME: &str = "album_maker.log";
+
+// records are targeted by module, eg "layer_hander" or "ffmpeg_interface"
+const CRATE_PREFIX: &str = "album_maker::";
+
+// writes log records to stderr, and optionally to a log file, filtering by a
+// default level with per module overrides
+pub struct Logger {
+    default_level: LevelFilter,
+    module_levels: Vec<(String, LevelFilter)>,
+    clear_line:    bool,
+    file:          Option<Mutex<File>>,
+}
+
+impl Logger {
+    pub fn new(default_level: LevelFilter) -> Self {
+        Self {
+            default_level,
+            module_levels: Vec::new(),
+            // a progress line may be drawn on stderr, clear it before logging
+            clear_line:    io::stderr().is_terminal(),
+            file:          None,
+        }
+    }
+
+    // parses a spec of the form "warn,layer_hander=debug,util=trace". A bare
+    // level sets the default, module=level overrides it for that module
+    pub fn parse_filters(mut self, spec: &str) -> Result<Self, String> {
+        for part in spec.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            match part.split_once('=') {
+                Some((module, level)) => {
+                    let level = level.parse::<LevelFilter>()
+                        .map_err(|_| format!("unknown log level {:?}", level))?;
+                    self.module_levels.push((module.trim().to_owned(), level));
+                },
+                None => {
+                    self.default_level = part.parse::<LevelFilter>()
+                        .map_err(|_| format!("unknown log level {:?}", part))?;
+                },
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn log_to_file(mut self, path: &Path) -> io::Result<Self> {
+        self.file = Some(Mutex::new(File::create(path)?));
+        Ok(self)
+    }
+
+    pub fn max_level(&self) -> LevelFilter {
+        self.module_levels.iter()
+            .map(|(_, level)| *level)
+            .fold(self.default_level, Ord::max)
+    }
+
+    pub fn install(self) {
+        let max_level = self.max_level();
+        log::set_boxed_logger(Box::new(self)).unwrap();
+        log::set_max_level(max_level);
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        let module = short_target(target);
+        // later filters take precedence over earlier ones
+        self.module_levels.iter()
+            .rev()
+            .find(|(name, _)| module == name || module.starts_with(&format!("{}::", name)))
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default_level)
+    }
+}
+
+fn short_target(target: &str) -> &str {
+    target.strip_prefix(CRATE_PREFIX).unwrap_or(target)
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let target = short_target(record.target());
+        let line = match record.level() {
+            Level::Info => format!("{}", record.args()),
+            level => format!("[{} {}] {}", level, target, record.args()),
+        };
+        let mut stderr = io::stderr();
+        if self.clear_line {
+            write!(stderr, "\r\x1b[2K").unwrap();
+        }
+        writeln!(stderr, "{}", line).unwrap();
+        if let Some(file) = &self.file {
+            let mut file = file.lock().unwrap();
+            let _ = writeln!(file, "[{} {}] {}", record.level(), target, record.args());
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap().flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn module_filters_override_default() {
+        let logger = Logger::new(LevelFilter::Info)
+            .parse_filters("warn,layer_hander=debug,util=trace").unwrap();
+        assert_eq!(logger.level_for("album_maker::layer_hander"), LevelFilter::Debug);
+        assert_eq!(logger.level_for("album_maker::util"), LevelFilter::Trace);
+        assert_eq!(logger.level_for("album_maker::ffmpeg_interface"), LevelFilter::Warn);
+        assert_eq!(logger.max_level(), LevelFilter::Trace);
+        assert!(Logger::new(LevelFilter::Info).parse_filters("util=loud").is_err());
+    }
+}
@@ -1,16 +1,45 @@
 use std::{
     path::PathBuf,
     env,
+    fs,
+    process,
+    thread,
 };
 use clap::{Arg, App};
+use log::{LevelFilter,debug,error,info,warn};
 
+mod album_config;
+mod captions;
+mod cleaner;
+mod export;
+mod feed;
+mod filters;
+mod formats;
+mod geo;
 mod html_builder;
 mod html_generation;
 mod util;
 mod layer_hander;
+mod manifest;
 mod ffmpeg_interface;
+mod logging;
+mod media;
+mod metadata;
+mod options;
+mod progress;
+mod raw;
+mod search;
+mod tags;
+mod timeline;
+mod thumbnail;
+mod traversal;
 
 use layer_hander::*;
+use options::{BuildOptions,OutputTarget};
+use progress::{Progress,Verbosity};
+use html_generation::Layout;
+use thumbnail::{CropAnchor,ThumbnailFormat,ThumbnailMode,ThumbnailSettings};
+use traversal::{Traversal,TraversalPolicy};
 
 fn main() {
     let matches = App::new("Album Builder")
@@ -42,34 +71,244 @@ fn main() {
                     .arg(Arg::with_name("threads")
                         .long("threads")
                         .value_name("THREADS")
-                        .help("Sets the max number of threads used for downsizing images")
-                        .takes_value(true)
-                        .default_value("1"))
+                        .help("Sets the number of threads used for downsizing images, defaults to the number of available cores")
+                        .takes_value(true))
                     .arg(Arg::with_name("im_width")
                         .long("im-width")
                         .value_name("WIDTH")
-                        .help("Sets the width of photos downsized")
+                        .help("Sets the size of thumbnails, their width, height or side depending on --thumb-mode")
                         .takes_value(true)
                         .default_value("500"))
+                    .arg(Arg::with_name("thumb_mode")
+                        .long("thumb-mode")
+                        .value_name("MODE")
+                        .help("Sets how photos are fitted to the thumbnail size, can be overridden by an album.toml")
+                        .takes_value(true)
+                        .possible_values(&thumbnail::MODE_NAMES)
+                        .case_insensitive(true)
+                        .default_value("width"))
+                    .arg(Arg::with_name("crop_anchor")
+                        .long("crop-anchor")
+                        .value_name("ANCHOR")
+                        .help("Sets which part of a photo square thumbnails keep, smart keeps the most detailed part")
+                        .takes_value(true)
+                        .possible_values(&thumbnail::ANCHOR_NAMES)
+                        .case_insensitive(true)
+                        .default_value("center"))
+                    .arg(Arg::with_name("output")
+                        .long("output")
+                        .value_name("TARGET")
+                        .help("Sets what is generated: html pages in every folder, or spa for an album.json per folder and a single page app at the top")
+                        .takes_value(true)
+                        .possible_values(&options::OUTPUT_NAMES)
+                        .case_insensitive(true)
+                        .default_value("html"))
+                    .arg(Arg::with_name("layout")
+                        .long("layout")
+                        .value_name("LAYOUT")
+                        .help("Sets how images are arranged on each page")
+                        .takes_value(true)
+                        .possible_values(&html_generation::LAYOUT_NAMES)
+                        .case_insensitive(true)
+                        .default_value("grid"))
+                    .arg(Arg::with_name("page_size")
+                        .long("page-size")
+                        .value_name("COUNT")
+                        .help("Splits albums with more than COUNT images over several pages, 0 keeps them on one")
+                        .takes_value(true)
+                        .default_value("0"))
+                    .arg(Arg::with_name("detail_pages")
+                        .long("detail-pages")
+                        .help("Generates a page for every photo with a larger copy, its details and links to its neighbours"))
+                    .arg(Arg::with_name("timeline")
+                        .long("timeline")
+                        .help("Also builds year, month and day pages from when photos were taken, in a timeline folder"))
+                    .arg(Arg::with_name("map")
+                        .long("map")
+                        .help("Adds a map of where geotagged photos were taken to each album, and writes their locations to locations.geojson"))
+                    .arg(Arg::with_name("map_tiles")
+                        .long("map-tiles")
+                        .value_name("URL")
+                        .help("Draws maps over tiles from this server, as a {z}/{x}/{y} template. Without it maps are static and work offline")
+                        .takes_value(true)
+                        .default_value(geo::DEFAULT_TILE_URL))
+                    .arg(Arg::with_name("map_attribution")
+                        .long("map-attribution")
+                        .value_name("TEXT")
+                        .help("Sets the credit shown under maps drawn over tiles, as the tile server's terms require. eg \"© OpenStreetMap contributors\"")
+                        .takes_value(true))
+                    .arg(Arg::with_name("feed")
+                        .long("feed")
+                        .help("Writes an Atom feed of the most recently changed albums to feed.xml"))
+                    .arg(Arg::with_name("base_url")
+                        .long("base-url")
+                        .value_name("URL")
+                        .help("Sets where the album tree is published, so the feed can link to it")
+                        .takes_value(true))
+                    .arg(Arg::with_name("feed_author")
+                        .long("feed-author")
+                        .value_name("NAME")
+                        .help("Sets who the feed is credited to")
+                        .takes_value(true)
+                        .default_value(feed::DEFAULT_FEED_AUTHOR))
+                    .arg(Arg::with_name("min_rating")
+                        .long("min-rating")
+                        .value_name("STARS")
+                        .help("Only includes photos rated at least STARS, from 0 to 5, in their xmp or iptc metadata")
+                        .takes_value(true))
+                    .arg(Arg::with_name("thumb_format")
+                        .long("thumb-format")
+                        .value_name("FORMAT")
+                        .help("Sets the format thumbnails are saved in: jpeg, png, webp or avif")
+                        .takes_value(true)
+                        .default_value("jpeg"))
+                    .arg(Arg::with_name("thumb_quality")
+                        .long("thumb-quality")
+                        .value_name("QUALITY")
+                        .help("Sets the quality thumbnails are encoded at, from 1 to 100")
+                        .takes_value(true)
+                        .default_value("85"))
+                    .arg(Arg::with_name("filter")
+                        .long("filter")
+                        .value_name("FILTER")
+                        .help("Sets the resampling filter used to shrink photos")
+                        .takes_value(true)
+                        .possible_values(&thumbnail::FILTER_NAMES)
+                        .case_insensitive(true)
+                        .default_value("triangle"))
+                    .arg(Arg::with_name("sharpen")
+                        .long("sharpen")
+                        .value_name("SIGMA")
+                        .help("Applies an unsharp mask of the given radius to thumbnails, 0.5 to 1.5 suits most photos")
+                        .takes_value(true))
+                    .arg(Arg::with_name("full_decode")
+                        .long("full-decode")
+                        .help("Decodes JPEGs at full resolution before shrinking them, slower but marginally sharper"))
                     .arg(Arg::with_name("clean")
                         .long("clean")
                         .help("Removes artifacts from this program, overides all other args"))
+                    .arg(Arg::with_name("prune_orphans")
+                        .long("prune-orphans")
+                        .requires("clean")
+                        .help("With --clean, only removes thumbnails whose source file no longer exists"))
+                    .arg(Arg::with_name("follow_symlinks")
+                        .long("follow-symlinks")
+                        .help("Follows symlinked folders and photos, each folder is only visited once"))
+                    .arg(Arg::with_name("one_file_system")
+                        .long("one-file-system")
+                        .help("Does not descend into folders on other filesystems"))
+                    .arg(Arg::with_name("hidden")
+                        .long("hidden")
+                        .help("Includes hidden files and folders"))
+                    .arg(Arg::with_name("include_junk")
+                        .long("include-junk")
+                        .help("Includes @eaDir, .thumbnails, Thumbs.db, lost+found and .DS_Store"))
+                    .arg(Arg::with_name("include")
+                        .long("include")
+                        .value_name("GLOB")
+                        .help("Only includes files matching GLOB, ignoring case, may be given more than once")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1))
+                    .arg(Arg::with_name("exclude")
+                        .long("exclude")
+                        .value_name("GLOB")
+                        .help("Excludes files and folders matching GLOB, ignoring case, may be given more than once")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1))
+                    .arg(Arg::with_name("quiet")
+                        .short("q")
+                        .long("quiet")
+                        .conflicts_with("verbose")
+                        .help("Only reports warnings and errors"))
+                    .arg(Arg::with_name("verbose")
+                        .short("v")
+                        .long("verbose")
+                        .multiple(true)
+                        .help("Reports every file as it is processed, repeat for trace output"))
+                    .arg(Arg::with_name("log")
+                        .long("log")
+                        .value_name("FILTER")
+                        .help("Sets log levels per module, eg \"warn,layer_hander=debug\"")
+                        .takes_value(true))
+                    .arg(Arg::with_name("log_file")
+                        .long("log-file")
+                        // a clean would remove the log along with the cacheDir
+                        .conflicts_with("clean")
+                        .help("Also writes the log to album_maker.log in the top level dir's cacheDir. Can't be used with --clean"))
+                    .arg(Arg::with_name("report")
+                        .long("report")
+                        .value_name("FILE")
+                        .help("Writes a JSON summary of the build to FILE")
+                        .takes_value(true))
+                    .arg(Arg::with_name("export")
+                        .long("export")
+                        .value_name("FILE")
+                        .help("Writes the album tree, its photos, thumbnails, dimensions and metadata, to FILE as JSON")
+                        .takes_value(true))
+                    .arg(Arg::with_name("formats")
+                        .long("formats")
+                        .help("Lists the image formats this build can read and write, and exits"))
                     .arg(Arg::with_name("local")
                         .long("local")
                         .help("Inserts js+css files into each directory, to allow other computers to access if shared over a network"))
                     .get_matches();
 
-    //set number of threads for rayon
-    let n_threads : u32 = matches.value_of("threads").unwrap_or_default().parse().unwrap();
-    let n_threads_str = format!("{}",n_threads);
-    env::set_var("RAYON_NUM_THREADS", n_threads_str);
+    if matches.index_of("formats").is_some() {
+        for format in formats::ALL_FORMATS.iter() {
+            println!("{:<6}{}", format.name(), format.capability());
+        }
+        println!("\nthumbnails can be written as");
+        for format in thumbnail::ALL_THUMBNAIL_FORMATS.iter() {
+            println!("{:<6}{}", format.name(), format.capability());
+        }
+        return;
+    }
+
+    // all parallel work runs in this one pool
+    let n_threads : usize = match matches.value_of("threads") {
+        Some(value) => match value.parse() {
+            Ok(value) => value,
+            Err(_) => panic!("did not understand threads arguement"),
+        },
+        None => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+    };
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(n_threads)
+        .build()
+        .unwrap();
 
     let clean : bool = match matches.index_of("clean") {
         Some(_count) => true,
         None => false,
     };
 
-    // let downsize_image_width: u32 = matches.value_of("im_width").unwrap_or_default().parse().unwrap();
+    let prune_orphans : bool = match matches.index_of("prune_orphans") {
+        Some(_count) => true,
+        None => false,
+    };
+
+    let thumbnail_size : u32 = match matches.value_of("im_width").unwrap_or_default().parse() {
+        Ok(value) if value > 0 => value,
+        _ => panic!("did not understand im-width arguement"),
+    };
+    let thumbnail_format = match ThumbnailFormat::from_name(matches.value_of("thumb_format").unwrap_or_default()) {
+        Some(format) if format.encodable() => format,
+        Some(format) => panic!("cannot write {} thumbnails: {}", format.name(), format.capability()),
+        None => panic!("did not understand thumb-format arguement"),
+    };
+    let thumbnail_quality : u8 = match matches.value_of("thumb_quality").unwrap_or_default().parse() {
+        Ok(value) if (1..=100).contains(&value) => value,
+        _ => panic!("did not understand thumb-quality arguement"),
+    };
+    let thumbnail_mode = ThumbnailMode::from_name(matches.value_of("thumb_mode").unwrap_or_default()).unwrap();
+    let crop_anchor = CropAnchor::from_name(matches.value_of("crop_anchor").unwrap_or_default()).unwrap();
+    let thumbnail_filter = thumbnail::parse_filter(matches.value_of("filter").unwrap_or_default()).unwrap();
+    let thumbnail_sharpen : Option<f32> = matches.value_of("sharpen").map(|value| match value.parse() {
+        Ok(value) if value >= 0.0 => value,
+        _ => panic!("did not understand sharpen arguement"),
+    });
     let local : bool = match matches.index_of("local") {
         Some(_count) => true,
         None => false,
@@ -80,15 +319,62 @@ fn main() {
         None => false,
     };
 
-    println!("force_regen: {:?}",force_regen);
-                    
+    let traversal_policy = TraversalPolicy {
+        follow_symlinks: matches.index_of("follow_symlinks").is_some(),
+        one_file_system: matches.index_of("one_file_system").is_some(),
+        include_hidden:  matches.index_of("hidden").is_some(),
+        include_junk:    matches.index_of("include_junk").is_some(),
+    };
+
+    let verbosity = if matches.index_of("quiet").is_some() {
+        Verbosity::Quiet
+    } else if matches.index_of("verbose").is_some() {
+        Verbosity::Verbose
+    } else {
+        Verbosity::Normal
+    };
+
     let tld = matches.value_of("dir").unwrap_or_default();
     let top_level_path = PathBuf::from(&tld).canonicalize().unwrap();
+
+    let log_level = match (verbosity, matches.occurrences_of("verbose")) {
+        (Verbosity::Quiet, _)   => LevelFilter::Warn,
+        (Verbosity::Normal, _)  => LevelFilter::Info,
+        (Verbosity::Verbose, 1) => LevelFilter::Debug,
+        (Verbosity::Verbose, _) => LevelFilter::Trace,
+    };
+    let mut logger = logging::Logger::new(log_level);
+    if let Some(spec) = matches.value_of("log") {
+        logger = match logger.parse_filters(spec) {
+            Ok(logger) => logger,
+            Err(reason) => panic!("did not understand log arguement: {}", reason),
+        };
+    }
+    if matches.index_of("log_file").is_some() {
+        let log_dir = top_level_path.join(cleaner::CACHE_DIR_NAME);
+        logger = match fs::create_dir_all(&log_dir).and_then(|_| logger.log_to_file(&log_dir.join(logging::LOG_FILE_NAME))) {
+            Ok(logger) => logger,
+            Err(reason) => panic!("could not open the log file: {}", reason),
+        };
+    }
+    logger.install();
+
+    debug!("force_regen: {:?}",force_regen);
+
     let search_depth : usize = 
         match matches.value_of("depth").unwrap_or_default().parse() {
             Ok(value) => value,
             Err(_) => panic!("did not understand depth arguement"),
         };
+
+    if clean {
+        let mut report = cleaner::CleanReport::new();
+        let mut traversal = Traversal::new(&traversal_policy, &top_level_path);
+        cleaner::clean_layer(&top_level_path, 0, search_depth, prune_orphans, &mut traversal, &mut report);
+        info!("removed {} items, reclaimed {}",
+            report.get_removed().len(), util::format_bytes(report.get_bytes_reclaimed()));
+        return;
+    }
         
         
         // let _fs = handle_layer(&top_level_path, 0, search_depth,clean,&resources_path,downsize_image_width);
@@ -115,13 +401,82 @@ fn main() {
         
 
     let use_ffmpeg = ffmpeg_interface::ffmpeg_available();
-    if use_ffmpeg {println!("using ffmpeg")} else {println!("no ffmpeg detected")}
-    
-    let _fs = handle_layer(&top_level_path, 0, search_depth,clean,&resources_path,local,force_regen,use_ffmpeg);
+    if use_ffmpeg {info!("using ffmpeg")} else {info!("no ffmpeg detected")}
+    debug!("using {} threads", n_threads);
+
+    let mut options = BuildOptions::new(resources_path);
+    options.max_depth   = search_depth;
+    options.make_local  = local;
+    options.force_regen = force_regen;
+    options.use_ffmpeg  = use_ffmpeg;
+    options.traversal   = traversal_policy;
+    options.include_globs = matches.values_of("include").map(|values| values.map(String::from).collect()).unwrap_or_default();
+    options.exclude_globs = matches.values_of("exclude").map(|values| values.map(String::from).collect()).unwrap_or_default();
+    options.output      = OutputTarget::from_name(matches.value_of("output").unwrap_or_default()).unwrap();
+    options.layout      = Layout::from_name(matches.value_of("layout").unwrap_or_default()).unwrap();
+    options.page_size   = match matches.value_of("page_size").unwrap_or_default().parse() {
+        Ok(value) => value,
+        Err(_) => panic!("did not understand page-size arguement"),
+    };
+    options.detail_pages = matches.is_present("detail_pages");
+    options.timeline    = matches.is_present("timeline");
+    options.map         = matches.is_present("map");
+    options.map_tiles   = matches.value_of("map_tiles").unwrap_or_default().to_owned();
+    options.map_attribution = matches.value_of("map_attribution").map(String::from);
+    if options.map && !options.map_tiles.is_empty() && options.map_attribution.is_none() {
+        warn!("map tiles usually have to be credited, set the credit with --map-attribution");
+    }
+    options.feed        = matches.is_present("feed");
+    options.base_url    = matches.value_of("base_url").map(String::from);
+    options.feed_author = matches.value_of("feed_author").unwrap_or_default().to_owned();
+    options.min_rating  = match matches.value_of("min_rating").map(str::parse::<i8>) {
+        Some(Ok(stars)) if (0..=5).contains(&stars) => Some(stars),
+        Some(_) => panic!("did not understand min-rating arguement"),
+        None => None,
+    };
+    options.thumbnails  = ThumbnailSettings {
+        size:    thumbnail_size,
+        mode:    thumbnail_mode,
+        anchor:  crop_anchor,
+        format:  thumbnail_format,
+        quality: thumbnail_quality,
+        filter:  thumbnail_filter,
+        sharpen: thumbnail_sharpen,
+        fast_jpeg: matches.index_of("full_decode").is_none(),
+    };
+
+    let progress = Progress::new(verbosity);
+    let fs = pool.install(|| {
+        handle_layer(&top_level_path, &options, &progress)
+    });
+    let albums = fs.as_ref().map(|record| record.get_all_records().len()).unwrap_or(0);
+    let summary = progress.finish(albums);
+    if verbosity >= Verbosity::Normal {
+        summary.print();
+    }
+    // failures are reported through the exit status too, for scripts and cron
+    let mut succeeded = summary.failed == 0 && summary.write_failed == 0;
+    if let Some(report_path) = matches.value_of("report") {
+        if let Err(reason) = summary.write_json(&PathBuf::from(report_path)) {
+            error!("{} - could not write the report: {}", report_path, reason);
+            succeeded = false;
+        }
+    }
+    if let (Some(export_path), Some(record)) = (matches.value_of("export"), &fs) {
+        if let Err(reason) = export::Export::new(record).write_json(&PathBuf::from(export_path)) {
+            error!("{} - could not write the export: {}", export_path, reason);
+            succeeded = false;
+        }
+    }
+    if !succeeded {
+        log::logger().flush();
+        process::exit(1);
+    }
 }
 
 
 #[derive(Clone,Debug)]
+#[allow(dead_code)]
 struct FSBranch {
     path: PathBuf,
     sub_dirs: Vec<FSBranch>
@@ -132,19 +487,40 @@ struct FSBranch {
 #[cfg(test)] 
 mod test {
     use super::*;
+    use std::path::Path;
     #[test]
     fn test_on_test_files() {
-        let n_threads : u32 = 4;
-        let n_threads_str = format!("{}",n_threads);
-        env::set_var("RAYON_NUM_THREADS", n_threads_str);
-
-        println!("RAYON_NUM_THREADS: {:?}",env::var("RAYON_NUM_THREADS").unwrap());
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
 
+        make_test_files(&PathBuf::from("./test_files"));
         let test_files_path = PathBuf::from("./test_files").canonicalize().unwrap();
-        let search_depth = 2;
-        let resources_path = PathBuf::from("./resources");
+        let mut options = BuildOptions::new(PathBuf::from("./resources"));
+        options.max_depth = 2;
         // let downsize_image_width = 500;
         // let _fs = handle_layer(&test_files_path, 0, search_depth,false,&resources_path,downsize_image_width);
-        let _fs = handle_layer(&test_files_path, 0, search_depth,false,&resources_path,false,false,false);
+        let fs = pool.install(|| {
+            handle_layer(&test_files_path, &options, &Progress::new(Verbosity::Quiet))
+        }).unwrap();
+        assert_eq!(fs.get_all_photos().len(), 2);
+        for pa in fs.get_all_photos() {
+            assert!(pa.get_abs_downsized().exists());
+            assert!(pa.get_thumbnail_size().is_some());
+        }
+        assert!(test_files_path.join("sub").join("index.html").exists());
+    }
+
+    // generates a small album to run against if one hasn't been provided
+    fn make_test_files(path: &Path) {
+        for (dir, name) in [("", "red.png"), ("sub", "green.jpg")].iter() {
+            let dir_path = path.join(dir);
+            std::fs::create_dir_all(&dir_path).unwrap();
+            let image_path = dir_path.join(name);
+            if !image_path.exists() {
+                image::RgbImage::from_pixel(800, 600, image::Rgb([200, 30, 30])).save(image_path).unwrap();
+            }
+        }
     }
 }
\ No newline at end of file
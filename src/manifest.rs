@@ -0,0 +1,109 @@
+use std::{
+    fs,
+    io,
+    path::Path,
+};
+
+use serde::Serialize;
+
+use super::cleaner::CACHE_DIR_NAME;
+use super::html_generation::get_first_photo;
+use super::progress::Progress;
+use super::util::{ActionRecord,PhotoAction,relative};
+
+// written into each album's cacheDir when building for the single page app
+pub const MANIFEST_FILE_NAME: &str = "album.json";
+
+// everything needed to show one album. Album paths are relative to the top of
+// the tree so the app can fetch them from wherever the shell is, and photos
+// are serialised as they are for --export, relative to their album
+#[derive(Serialize)]
+pub struct AlbumManifest<'a> {
+    pub name:        String,
+    // "" for the top album
+    pub path:        String,
+    pub parent:      Option<String>,
+    // the album's README.md, rendered to html
+    pub description: Option<String>,
+    pub albums:      Vec<AlbumEntry>,
+    pub photos:      Vec<&'a PhotoAction>,
+}
+
+#[derive(Debug,Serialize)]
+pub struct AlbumEntry {
+    pub name:   String,
+    pub path:   String,
+    pub cover:  Option<String>,
+    pub photos: usize,
+}
+
+impl<'a> AlbumManifest<'a> {
+    pub fn new(root: &Path, ar: &'a ActionRecord) -> Self {
+        let path = ar.get_path();
+        Self {
+            name:        album_name(&path),
+            path:        relative(root, &path),
+            parent:      if ar.get_depth() == 0 { None } else { path.parent().map(|parent| relative(root, parent)) },
+            description: ar.get_description().map(str::to_owned),
+            albums:      ar.get_subdirs().iter().map(|sub_dir| AlbumEntry {
+                name:   album_name(&sub_dir.get_path()),
+                path:   relative(root, &sub_dir.get_path()),
+                cover:  get_first_photo(sub_dir).map(|pa| relative(root, &pa.get_abs_downsized())),
+                photos: sub_dir.get_all_photos().len(),
+            }).collect(),
+            photos:      ar.get_photos().iter().collect(),
+        }
+    }
+
+    // serialised before the file is created, so nothing is left half written
+    pub fn write_json(&self, manifest_path: &Path) -> io::Result<()> {
+        fs::write(manifest_path, serde_json::to_vec(self)?)
+    }
+}
+
+// writes a manifest into the cacheDir of every album in the tree, reporting
+// any that can't be written. Returns how many were written
+pub fn write_manifests(root: &ActionRecord, progress: &Progress) -> usize {
+    let root_path = root.get_path();
+    root.get_all_records().into_iter()
+        .filter(|ar| {
+            let manifest_path = ar.get_path().join(CACHE_DIR_NAME).join(MANIFEST_FILE_NAME);
+            match AlbumManifest::new(&root_path, ar).write_json(&manifest_path) {
+                Ok(()) => true,
+                Err(reason) => {
+                    progress.record_write_failure(&manifest_path, &reason);
+                    false
+                },
+            }
+        })
+        .count()
+}
+
+fn album_name(path: &Path) -> String {
+    path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::test::album_fixture;
+
+    #[test]
+    fn describes_each_album() {
+        let root_path = Path::new("/album");
+        let root = album_fixture();
+
+        let top = AlbumManifest::new(root_path, &root);
+        assert_eq!((top.path.as_str(), top.parent.as_deref()), ("", None));
+        assert_eq!(top.albums.len(), 1);
+        assert_eq!(top.albums[0].cover.as_deref(), Some("trip/cacheDir/boat.jpg.500w.jpg"));
+        assert_eq!(top.albums[0].photos, 1);
+
+        let trip = AlbumManifest::new(root_path, &root.get_subdirs()[0]);
+        assert_eq!((trip.name.as_str(), trip.parent.as_deref()), ("trip", Some("")));
+        assert_eq!(trip.photos[0].get_caption(), Some("at dusk"));
+        let photo = &serde_json::to_value(&trip).unwrap()["photos"][0];
+        assert_eq!((&photo["file"], &photo["thumbnail"]), (&serde_json::json!("boat.jpg"), &serde_json::json!("cacheDir/boat.jpg.500w.jpg")));
+        assert!(photo.get("dir").is_none());
+    }
+}
@@ -0,0 +1,254 @@
+use std::{
+    fs::File,
+    io::Read,
+    path::Path,
+};
+
+use serde::Serialize;
+
+use super::formats::PhotoFormat;
+
+// how many bytes are read from the start of a file to identify it
+const SNIFF_LENGTH: usize = 32;
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Image,
+    Video,
+    Audio,
+    Raw,
+    Document,
+    Unknown,
+}
+
+// the first bytes of a file and its length, read once and shared by
+// everything that identifies it
+pub struct FileHeader {
+    bytes:    [u8; SNIFF_LENGTH],
+    len:      usize,
+    file_len: u64,
+}
+
+impl FileHeader {
+    // files that can't be read give an empty header
+    pub fn read(path: &Path) -> Self {
+        let mut bytes = [0u8; SNIFF_LENGTH];
+        let (len, file_len) = match File::open(path) {
+            Ok(mut file) => {
+                let file_len = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+                (read_up_to(&mut file, &mut bytes), file_len)
+            },
+            Err(_) => (0, 0),
+        };
+        Self { bytes, len, file_len }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    pub fn file_len(&self) -> u64 {
+        self.file_len
+    }
+
+    #[cfg(test)]
+    pub fn from_bytes(bytes: &[u8], file_len: u64) -> Self {
+        let mut header = Self { bytes: [0; SNIFF_LENGTH], len: bytes.len(), file_len };
+        header.bytes[..bytes.len()].copy_from_slice(bytes);
+        header
+    }
+}
+
+// what a file holds and, for images, their format
+pub type Identity = (MediaKind, Option<PhotoFormat>);
+
+// works out what sort of media a file holds, from its first few bytes where
+// they are recognisable and its extension (in any case) otherwise
+pub fn identify(path: &Path) -> Identity {
+    identify_header(&FileHeader::read(path), path)
+}
+
+pub fn identify_header(header: &FileHeader, path: &Path) -> Identity {
+    let by_extension = identify_extension(path);
+    match (sniff(header), by_extension) {
+        // most raw formats are tiff containers, trust the extension to tell them apart
+        (Some((MediaKind::Image, _)), Some((MediaKind::Raw, _))) if is_tiff(header.bytes()) => (MediaKind::Raw, None),
+        (Some(identity), _) => identity,
+        (None, Some(identity)) => identity,
+        // signatures short enough to turn up in any file are only trusted when
+        // the extension doesn't say otherwise
+        (None, None) => (sniff_weak(header.bytes()).unwrap_or(MediaKind::Unknown), None),
+    }
+}
+
+pub fn identify_extension(path: &Path) -> Option<Identity> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    let identity = match extension.as_str() {
+        "jpg" | "jpeg" | "jpe" => (MediaKind::Image, Some(PhotoFormat::Jpeg)),
+        "png"  => (MediaKind::Image, Some(PhotoFormat::Png)),
+        "gif"  => (MediaKind::Image, Some(PhotoFormat::Gif)),
+        "webp" => (MediaKind::Image, Some(PhotoFormat::WebP)),
+        "tif" | "tiff" => (MediaKind::Image, Some(PhotoFormat::Tiff)),
+        "bmp"  => (MediaKind::Image, Some(PhotoFormat::Bmp)),
+        "avif" => (MediaKind::Image, Some(PhotoFormat::Avif)),
+        "heic" | "heif" => (MediaKind::Image, Some(PhotoFormat::Heif)),
+        "cr2" | "cr3" | "crw" | "nef" | "nrw" | "arw" | "srf" | "sr2" | "dng" |
+        "orf" | "rw2" | "raf" | "pef" | "srw" | "x3f" | "3fr" | "rwl" => (MediaKind::Raw, None),
+        "mp4" | "m4v" | "mov" | "avi" | "mkv" | "webm" | "mts" | "m2ts" |
+        "mpg" | "mpeg" | "wmv" | "flv" | "3gp" => (MediaKind::Video, None),
+        "mp3" | "m4a" | "aac" | "flac" | "wav" | "ogg" | "opus" | "wma" => (MediaKind::Audio, None),
+        "pdf" | "txt" | "md" | "doc" | "docx" | "odt" | "rtf" | "csv" |
+        "toml" | "xmp" | "html" | "htm" | "json" | "xml" => (MediaKind::Document, None),
+        _ => return None,
+    };
+    Some(identity)
+}
+
+// identifies a file from magic bytes long or specific enough not to turn up by
+// chance, so they can be trusted over the extension
+pub fn sniff(file_header: &FileHeader) -> Option<Identity> {
+    let header = file_header.bytes();
+    let starts = |magic: &[u8]| header.starts_with(magic);
+    let at = |offset: usize, magic: &[u8]| header.len() >= offset+magic.len() && &header[offset..offset+magic.len()] == magic;
+    let image = |format: PhotoFormat| Some((MediaKind::Image, Some(format)));
+
+    if starts(&[0xFF, 0xD8, 0xFF]) {
+        return image(PhotoFormat::Jpeg);
+    }
+    if starts(b"\x89PNG\r\n\x1a\n") {
+        return image(PhotoFormat::Png);
+    }
+    if starts(b"GIF87a") || starts(b"GIF89a") {
+        return image(PhotoFormat::Gif);
+    }
+    if starts(b"RIFF") {
+        return match header.get(8..12) {
+            Some(b"WEBP") => image(PhotoFormat::WebP),
+            Some(b"AVI ") => Some((MediaKind::Video, None)),
+            Some(b"WAVE") => Some((MediaKind::Audio, None)),
+            _ => None,
+        };
+    }
+    // canon CR2, olympus ORF, panasonic RW2 and fuji RAF have their own signatures
+    if (starts(b"II*\0") && at(8, b"CR")) || starts(b"IIRO") || starts(b"IIRS") || starts(b"IIU\0") || starts(b"FUJIFILMCCD-RAW") {
+        return Some((MediaKind::Raw, None));
+    }
+    if is_tiff(header) {
+        return image(PhotoFormat::Tiff);
+    }
+    if is_bmp(file_header) {
+        return image(PhotoFormat::Bmp);
+    }
+    // iso base media files, identified by the brand in their ftyp box
+    if at(4, b"ftyp") {
+        return match header.get(8..12) {
+            Some(b"avif") | Some(b"avis") => image(PhotoFormat::Avif),
+            Some(b"heic") | Some(b"heix") | Some(b"hevc") | Some(b"heim") |
+            Some(b"heis") | Some(b"mif1") | Some(b"msf1") => image(PhotoFormat::Heif),
+            Some(b"crx ") => Some((MediaKind::Raw, None)),
+            Some(b"M4A ") | Some(b"M4B ") => Some((MediaKind::Audio, None)),
+            _ => Some((MediaKind::Video, None)),
+        };
+    }
+    if starts(&[0x1A, 0x45, 0xDF, 0xA3]) || starts(&[0x00, 0x00, 0x01, 0xBA]) || starts(b"FLV\x01")
+        || starts(&[0x30, 0x26, 0xB2, 0x75, 0x8E, 0x66, 0xCF, 0x11]) {
+        return Some((MediaKind::Video, None));
+    }
+    if starts(b"ID3") || starts(b"fLaC") || starts(b"OggS") {
+        return Some((MediaKind::Audio, None));
+    }
+    if starts(b"%PDF") || starts(b"PK\x03\x04") || starts(b"{\\rtf") {
+        return Some((MediaKind::Document, None));
+    }
+    None
+}
+
+// an mp3 frame without an id3 tag starts with just two bytes of sync word
+fn sniff_weak(header: &[u8]) -> Option<MediaKind> {
+    if header.len() >= 2 && header[0] == 0xFF && [0xFB, 0xF3, 0xF2].contains(&header[1]) {
+        Some(MediaKind::Audio)
+    } else {
+        None
+    }
+}
+
+// "BM" alone turns up at the start of plenty of text, so the file size the
+// header gives and the length of the dib header that follows it are checked too
+fn is_bmp(file_header: &FileHeader) -> bool {
+    let header = file_header.bytes();
+    if !header.starts_with(b"BM") || header.len() < 18 {
+        return false;
+    }
+    let le_u32 = |offset: usize| u32::from_le_bytes([header[offset], header[offset+1], header[offset+2], header[offset+3]]);
+    le_u32(2) as u64 == file_header.file_len() && [12, 40, 52, 56, 64, 108, 124].contains(&le_u32(14))
+}
+
+fn is_tiff(header: &[u8]) -> bool {
+    header.starts_with(b"II*\0") || header.starts_with(b"MM\0*")
+}
+
+fn read_up_to<R: Read>(reader: &mut R, buffer: &mut [u8]) -> usize {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) | Err(_) => break,
+            Ok(count) => filled += count,
+        }
+    }
+    filled
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{fs,path::PathBuf};
+
+    #[test]
+    fn sniffs_magic_bytes() {
+        let sniff_bytes = |bytes: &[u8]| sniff(&FileHeader::from_bytes(bytes, 1000));
+        assert_eq!(sniff_bytes(&[0xFF, 0xD8, 0xFF, 0xE1]), Some((MediaKind::Image, Some(PhotoFormat::Jpeg))));
+        assert_eq!(sniff_bytes(b"GIF89a\x01\0\x01\0"), Some((MediaKind::Image, Some(PhotoFormat::Gif))));
+        assert_eq!(sniff_bytes(b"RIFF\0\0\0\0WEBPVP8 "), Some((MediaKind::Image, Some(PhotoFormat::WebP))));
+        assert_eq!(sniff_bytes(b"II*\0\x10\0\0\0CR\x02\0"), Some((MediaKind::Raw, None)));
+        assert_eq!(sniff_bytes(b"\0\0\0\x18ftypqt  "), Some((MediaKind::Video, None)));
+        assert_eq!(sniff_bytes(b"\0\0\0\x1cftypavif"), Some((MediaKind::Image, Some(PhotoFormat::Avif))));
+        assert_eq!(sniff_bytes(b"\0\0\0\x18ftypheic"), Some((MediaKind::Image, Some(PhotoFormat::Heif))));
+        assert_eq!(sniff_bytes(b"%PDF-1.4"), Some((MediaKind::Document, None)));
+        assert_eq!(sniff_bytes(b"hello"), None);
+        // a bitmap's header has to agree with the file's length
+        let bmp = b"BM\xe8\x03\0\0\0\0\0\0\x36\0\0\0\x28\0\0\0";
+        assert_eq!(sniff(&FileHeader::from_bytes(bmp, 1000)), Some((MediaKind::Image, Some(PhotoFormat::Bmp))));
+        assert_eq!(sniff(&FileHeader::from_bytes(bmp, 999)), None);
+        assert_eq!(sniff_bytes(b"BMW owners club minutes"), None);
+        assert_eq!(sniff_bytes(&[0xFF, 0xFB, 0x90, 0x44]), None);
+    }
+
+    #[test]
+    fn classifies_files() {
+        let dir = PathBuf::from("./test_files_out/media");
+        fs::create_dir_all(&dir).unwrap();
+        let cases : [(&str, &[u8], Identity); 11] = [
+            // upper case extensions straight off a camera
+            ("IMG_0001.JPG", &[0xFF, 0xD8, 0xFF, 0xE0], (MediaKind::Image, Some(PhotoFormat::Jpeg))),
+            ("IMG_0001.HEIC", b"unrecognisable", (MediaKind::Image, Some(PhotoFormat::Heif))),
+            // a tiff container with a raw extension
+            ("DSC_0001.NEF", b"MM\0*\0\0\0\x08", (MediaKind::Raw, None)),
+            // content wins over a misleading extension
+            ("notes.jpg", b"%PDF-1.7", (MediaKind::Document, None)),
+            ("actually_png.jpg", b"\x89PNG\r\n\x1a\n", (MediaKind::Image, Some(PhotoFormat::Png))),
+            ("notes.txt", b"just some text", (MediaKind::Document, None)),
+            // short signatures don't override the extension
+            ("BMW.txt", b"BMW service history", (MediaKind::Document, None)),
+            ("sync.txt", &[0xFF, 0xFB, 0x90, 0x44, 0x20], (MediaKind::Document, None)),
+            ("track", &[0xFF, 0xFB, 0x90, 0x44, 0x20], (MediaKind::Audio, None)),
+            ("clip.MOV", b"unrecognisable", (MediaKind::Video, None)),
+            ("mystery", b"unrecognisable", (MediaKind::Unknown, None)),
+        ];
+        for (name, content, identity) in cases.iter() {
+            let path = dir.join(name);
+            fs::write(&path, content).unwrap();
+            assert_eq!(identify(&path), *identity, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,456 @@
+use std::{
+    collections::HashMap,
+    fs::{self,File},
+    io::{BufReader,Read},
+    path::Path,
+    sync::{Mutex,OnceLock},
+};
+
+use chrono::{NaiveDate,NaiveDateTime};
+use exif::{Exif,Field,In,Tag,Value};
+use regex::Regex;
+use serde::Serialize;
+
+// embedded xmp sits near the start of the files cameras and editors write, so
+// only this much of a file is searched for it
+const XMP_SEARCH_LIMIT: u64 = 1 << 20;
+
+// the exif details shown on a photo's detail page
+#[derive(Clone,Debug,Default,PartialEq,Serialize)]
+pub struct PhotoMetadata {
+    pub camera:       Option<String>,
+    pub lens:         Option<String>,
+    pub taken:        Option<String>,
+    pub taken_at:     Option<NaiveDateTime>,
+    pub exposure:     Option<String>,
+    pub aperture:     Option<String>,
+    pub iso:          Option<String>,
+    pub focal_length: Option<String>,
+    pub description:  Option<String>,
+    // latitude and longitude in degrees, south and west negative
+    pub location:     Option<(f64, f64)>,
+}
+
+// reads the exif block of a jpeg, tiff, heif or tiff based raw file. Files
+// without one give None
+pub fn read_metadata(path: &Path) -> Option<PhotoMetadata> {
+    let file = File::open(path).ok()?;
+    let exif = exif::Reader::new().read_from_container(&mut BufReader::new(file)).ok()?;
+    Some(PhotoMetadata::from_exif(&exif))
+}
+
+impl PhotoMetadata {
+    pub fn from_exif(exif: &Exif) -> Self {
+        let text = |tag| exif.get_field(tag, In::PRIMARY).and_then(ascii_value);
+        let display = |tag| exif.get_field(tag, In::PRIMARY)
+            .map(|field| field.display_value().with_unit(exif).to_string());
+        // most makers repeat their name at the start of the model
+        let camera = match (text(Tag::Make), text(Tag::Model)) {
+            (Some(make), Some(model)) if model.to_lowercase().starts_with(&make.to_lowercase()) => Some(model),
+            (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+            (make, model) => make.or(model),
+        };
+        let taken_field = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY)
+            .or_else(|| exif.get_field(Tag::DateTime, In::PRIMARY));
+        Self {
+            camera,
+            lens:         text(Tag::LensModel),
+            taken:        taken_field.and_then(ascii_value),
+            taken_at:     taken_field.and_then(date_time_value),
+            exposure:     display(Tag::ExposureTime),
+            aperture:     display(Tag::FNumber),
+            iso:          display(Tag::PhotographicSensitivity),
+            focal_length: display(Tag::FocalLength),
+            description:  text(Tag::ImageDescription),
+            location:     gps_coordinate(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, "S")
+                .zip(gps_coordinate(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, "W")),
+        }
+    }
+
+    // label and value of every detail that is known, in display order
+    pub fn summary(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("Camera",       &self.camera),
+            ("Lens",         &self.lens),
+            ("Taken",        &self.taken),
+            ("Exposure",     &self.exposure),
+            ("Aperture",     &self.aperture),
+            ("ISO",          &self.iso),
+            ("Focal length", &self.focal_length),
+        ].iter()
+            .filter_map(|(label, value)| value.as_deref().map(|value| (*label, value)))
+            .collect()
+    }
+}
+
+// what photo managers record about a photo in xmp or iptc
+#[derive(Clone,Debug,Default,PartialEq,Serialize)]
+pub struct PhotoTags {
+    // 0 to 5 stars, -1 for rejected
+    pub rating:      Option<i8>,
+    // a colour label such as red or green
+    pub label:       Option<String>,
+    pub title:       Option<String>,
+    pub description: Option<String>,
+    pub keywords:    Vec<String>,
+}
+
+impl PhotoTags {
+    pub fn from_xmp(xmp: &str) -> Self {
+        Self {
+            rating:      xmp_simple(xmp, "xmp:Rating")
+                .and_then(|rating| rating.parse::<f32>().ok())
+                .map(|rating| rating.round().clamp(-1.0, 5.0) as i8),
+            label:       xmp_simple(xmp, "xmp:Label"),
+            title:       xmp_alt_text(xmp, "dc:title"),
+            description: xmp_alt_text(xmp, "dc:description"),
+            keywords:    xmp_bag(xmp, "dc:subject"),
+        }
+    }
+
+    // an iptc-iim block, as photoshop embeds in jpegs. Only the title,
+    // keywords and caption datasets are read
+    pub fn from_iptc(iim: &[u8]) -> Self {
+        let mut tags = Self::default();
+        let mut at = 0;
+        while at+5 <= iim.len() && iim[at] == 0x1c {
+            let size = u16::from_be_bytes([iim[at+3], iim[at+4]]) as usize;
+            // extended datasets are only used for large binary values
+            if size & 0x8000 != 0 {
+                break;
+            }
+            let value = match iim.get(at+5..at+5+size) {
+                Some(value) => String::from_utf8_lossy(value).trim().to_owned(),
+                None => break,
+            };
+            if !value.is_empty() {
+                match (iim[at+1], iim[at+2]) {
+                    (2, 5)   => tags.title = Some(value),
+                    (2, 25)  => tags.keywords.push(value),
+                    (2, 120) => tags.description = Some(value),
+                    _ => {},
+                }
+            }
+            at += 5+size;
+        }
+        tags
+    }
+
+    // anything not known here is taken from `other`
+    fn or(self, other: PhotoTags) -> PhotoTags {
+        PhotoTags {
+            rating:      self.rating.or(other.rating),
+            label:       self.label.or(other.label),
+            title:       self.title.or(other.title),
+            description: self.description.or(other.description),
+            keywords:    if self.keywords.is_empty() { other.keywords } else { self.keywords },
+        }
+    }
+
+    // adds keywords that aren't already there, ignoring case
+    pub fn add_keywords(&mut self, keywords: &[String]) {
+        for keyword in keywords {
+            if !self.keywords.iter().any(|existing| existing.eq_ignore_ascii_case(keyword)) {
+                self.keywords.push(keyword.clone());
+            }
+        }
+    }
+
+    // unrated photos count as 0 stars
+    pub fn stars(&self) -> i8 {
+        self.rating.unwrap_or(0)
+    }
+
+    // label and value of every tag that is known, in display order
+    pub fn summary(&self) -> Vec<(&'static str, String)> {
+        let mut summary = Vec::new();
+        match self.rating {
+            Some(-1) => summary.push(("Rating", String::from("rejected"))),
+            Some(rating) if rating > 0 => summary.push(("Rating", star_string(rating))),
+            _ => {},
+        }
+        if let Some(label) = &self.label {
+            summary.push(("Label", label.clone()));
+        }
+        if !self.keywords.is_empty() {
+            summary.push(("Keywords", self.keywords.join(", ")));
+        }
+        summary
+    }
+}
+
+// filled and empty stars out of five
+pub fn star_string(rating: i8) -> String {
+    let rating = rating.clamp(0, 5) as usize;
+    format!("{}{}", "\u{2605}".repeat(rating), "\u{2606}".repeat(5-rating))
+}
+
+// a photo's tags from its xmp sidecar, falling back to the xmp and then the
+// iptc embedded in the photo for anything the sidecar doesn't say
+pub fn read_tags(path: &Path) -> PhotoTags {
+    let mut tags = read_xmp_sidecar(path)
+        .map(|xmp| PhotoTags::from_xmp(&xmp))
+        .unwrap_or_default();
+    if let Some(head) = read_head(path) {
+        if let Some(xmp) = embedded_xmp(&head) {
+            tags = tags.or(PhotoTags::from_xmp(&xmp));
+        }
+        if let Some(iim) = embedded_iptc(&head) {
+            tags = tags.or(PhotoTags::from_iptc(iim));
+        }
+    }
+    tags
+}
+
+// the xmp packet in a sidecar beside the photo, named either photo.xmp or
+// photo.jpg.xmp depending on the program that wrote it
+pub fn read_xmp_sidecar(path: &Path) -> Option<String> {
+    let with_extension = format!("{}.xmp", path.file_name()?.to_string_lossy());
+    [path.with_file_name(with_extension), path.with_extension("xmp")].iter()
+        .find_map(|sidecar| fs::read_to_string(sidecar).ok())
+}
+
+fn read_head(path: &Path) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    File::open(path).ok()?.take(XMP_SEARCH_LIMIT).read_to_end(&mut bytes).ok()?;
+    Some(bytes)
+}
+
+fn embedded_xmp(head: &[u8]) -> Option<String> {
+    let start = find_bytes(head, b"<x:xmpmeta")?;
+    let end = start + find_bytes(&head[start..], b"</x:xmpmeta>")?;
+    Some(String::from_utf8_lossy(&head[start..end]).into_owned())
+}
+
+// the iptc block from the photoshop image resources in a jpeg's app13 segment
+fn embedded_iptc(head: &[u8]) -> Option<&[u8]> {
+    const SIGNATURE: &[u8] = b"Photoshop 3.0\0";
+    let mut resources = &head[find_bytes(head, SIGNATURE)? + SIGNATURE.len()..];
+    while resources.starts_with(b"8BIM") {
+        let id = u16::from_be_bytes([*resources.get(4)?, *resources.get(5)?]);
+        // a pascal string name, padded to an even length
+        let size_at = 6 + ((*resources.get(6)? as usize + 2) & !1);
+        let size_bytes = resources.get(size_at..size_at+4)?;
+        let size = u32::from_be_bytes([size_bytes[0], size_bytes[1], size_bytes[2], size_bytes[3]]) as usize;
+        let data = resources.get(size_at+4..size_at+4+size)?;
+        if id == 0x0404 {
+            return Some(data);
+        }
+        resources = resources.get(size_at + 4 + ((size+1) & !1)..)?;
+    }
+    None
+}
+
+// a language alternative property such as dc:description or dc:title. The
+// first entry is used, which is the default language
+pub fn xmp_alt_text(xmp: &str, property: &str) -> Option<String> {
+    let pattern = property_pattern(r"(?s)<{0}[^>]*>.*?<rdf:li[^>]*>(.*?)</rdf:li>.*?</{0}>", property);
+    pattern.captures(xmp)
+        .map(|captures| xml_unescape(captures[1].trim()))
+        .filter(|text| !text.is_empty())
+}
+
+// a simple property, written either as an attribute or an element
+fn xmp_simple(xmp: &str, property: &str) -> Option<String> {
+    let pattern = property_pattern(r#"(?s){0}="([^"]*)"|<{0}>([^<]*)</{0}>"#, property);
+    pattern.captures(xmp)
+        .and_then(|captures| captures.get(1).or_else(|| captures.get(2)))
+        .map(|value| xml_unescape(value.as_str().trim()))
+        .filter(|value| !value.is_empty())
+}
+
+// every entry of an unordered list property, such as dc:subject
+fn xmp_bag(xmp: &str, property: &str) -> Vec<String> {
+    static ITEM: OnceLock<Regex> = OnceLock::new();
+    let pattern = property_pattern(r"(?s)<{0}[^>]*>(.*?)</{0}>", property);
+    let item = ITEM.get_or_init(|| Regex::new(r"(?s)<rdf:li[^>]*>(.*?)</rdf:li>").unwrap());
+    match pattern.captures(xmp) {
+        Some(captures) => item.captures_iter(&captures[1])
+            .map(|item| xml_unescape(item[1].trim()))
+            .filter(|keyword| !keyword.is_empty())
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+// `template` with {0} standing for the property's name. The same few
+// properties are looked up in every photo, so each pattern is only compiled once
+fn property_pattern(template: &'static str, property: &str) -> Regex {
+    static PATTERNS: OnceLock<Mutex<HashMap<(&'static str, String), Regex>>> = OnceLock::new();
+    let mut patterns = PATTERNS.get_or_init(Default::default).lock().unwrap();
+    patterns.entry((template, property.to_owned()))
+        .or_insert_with(|| Regex::new(&template.replace("{0}", &regex::escape(property))).unwrap())
+        .clone()
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn xml_unescape(text: &str) -> String {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| Regex::new(r"&(amp|lt|gt|quot|apos|#x[0-9a-fA-F]+|#[0-9]+);").unwrap());
+    pattern.replace_all(text, |captures: &regex::Captures| {
+        let entity = &captures[1];
+        let character = match entity {
+            "amp"  => Some('&'),
+            "lt"   => Some('<'),
+            "gt"   => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(char::from_u32),
+            _ => entity[1..].parse().ok().and_then(char::from_u32),
+        };
+        character.map(String::from).unwrap_or_else(|| captures[0].to_owned())
+    }).into_owned()
+}
+
+// the first string of an ascii field, with the padding some cameras add
+// trimmed off. Empty strings count as missing
+fn ascii_value(field: &Field) -> Option<String> {
+    match field.value {
+        Value::Ascii(ref strings) => strings.first()
+            .map(|bytes| String::from_utf8_lossy(bytes).trim_matches(|c: char| c.is_whitespace() || c == '\0').to_owned())
+            .filter(|string| !string.is_empty()),
+        _ => None,
+    }
+}
+
+// degrees, minutes and seconds, negated for the `negative` hemisphere
+fn gps_coordinate(exif: &Exif, tag: Tag, reference_tag: Tag, negative: &str) -> Option<f64> {
+    let parts = match exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(ref parts) if parts.len() == 3 && parts.iter().all(|part| part.denom != 0) => parts,
+        _ => return None,
+    };
+    let degrees = parts[0].to_f64() + parts[1].to_f64()/60.0 + parts[2].to_f64()/3600.0;
+    let reference = exif.get_field(reference_tag, In::PRIMARY).and_then(ascii_value);
+    match reference {
+        Some(reference) if reference.eq_ignore_ascii_case(negative) => Some(-degrees),
+        _ => Some(degrees),
+    }
+}
+
+// exif dates are local time with no zone, as is NaiveDateTime
+fn date_time_value(field: &Field) -> Option<NaiveDateTime> {
+    let bytes = match field.value {
+        Value::Ascii(ref strings) => strings.first()?,
+        _ => return None,
+    };
+    let date_time = exif::DateTime::from_ascii(bytes).ok()?;
+    NaiveDate::from_ymd_opt(date_time.year as i32, date_time.month as u32, date_time.day as u32)?
+        .and_hms_opt(date_time.hour as u32, date_time.minute as u32, date_time.second as u32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // a little endian tiff header and one ifd holding the given ascii fields
+    fn tiff_with_ascii(fields: &[(u16, &str)]) -> Vec<u8> {
+        let mut tiff = vec![b'I', b'I', 42, 0, 8, 0, 0, 0];
+        tiff.extend(&(fields.len() as u16).to_le_bytes());
+        let mut data_offset = 8 + 2 + fields.len()*12 + 4;
+        let mut data = Vec::new();
+        for (tag, value) in fields {
+            let count = value.len() as u32 + 1;
+            tiff.extend(&tag.to_le_bytes());
+            tiff.extend(&2u16.to_le_bytes());
+            tiff.extend(&count.to_le_bytes());
+            tiff.extend(&(data_offset as u32).to_le_bytes());
+            data.extend(value.as_bytes());
+            data.push(0);
+            data_offset += count as usize;
+        }
+        tiff.extend(&0u32.to_le_bytes());
+        tiff.extend(data);
+        tiff
+    }
+
+    #[test]
+    fn reads_ascii_fields() {
+        let tiff = tiff_with_ascii(&[
+            (0x010e, "  harbour at dusk  "),
+            (0x010f, "Canon"),
+            (0x0110, "Canon EOS 80D"),
+            (0x0132, "2021:06:01 18:30:00"),
+        ]);
+        let exif = exif::Reader::new().read_raw(tiff).unwrap();
+        let metadata = PhotoMetadata::from_exif(&exif);
+        assert_eq!(metadata.camera.as_deref(), Some("Canon EOS 80D"));
+        assert_eq!(metadata.description.as_deref(), Some("harbour at dusk"));
+        assert_eq!(metadata.taken.as_deref(), Some("2021:06:01 18:30:00"));
+        assert_eq!(metadata.taken_at, NaiveDate::from_ymd_opt(2021, 6, 1).unwrap().and_hms_opt(18, 30, 0));
+        assert_eq!(metadata.summary(), vec![("Camera", "Canon EOS 80D"), ("Taken", "2021:06:01 18:30:00")]);
+    }
+
+    #[test]
+    fn reads_gps_location() {
+        // ifd0 holds only the pointer to the gps ifd, which follows it
+        let rational = |parts: [(u32, u32); 3]| -> Vec<u8> {
+            parts.iter().flat_map(|(num, denom)| num.to_le_bytes().iter().chain(denom.to_le_bytes().iter()).copied().collect::<Vec<u8>>()).collect()
+        };
+        let mut tiff = vec![b'I', b'I', 42, 0, 8, 0, 0, 0, 1, 0];
+        tiff.extend(&[0x25, 0x88, 4, 0, 1, 0, 0, 0, 26, 0, 0, 0, 0, 0, 0, 0]);
+        let data_offset : u32 = 26 + 2 + 4*12 + 4;
+        tiff.extend(&[4, 0]);
+        tiff.extend(&[1, 0, 2, 0, 2, 0, 0, 0, b'S', 0, 0, 0]);
+        tiff.extend(&[2, 0, 5, 0, 3, 0, 0, 0]);
+        tiff.extend(&data_offset.to_le_bytes());
+        tiff.extend(&[3, 0, 2, 0, 2, 0, 0, 0, b'E', 0, 0, 0]);
+        tiff.extend(&[4, 0, 5, 0, 3, 0, 0, 0]);
+        tiff.extend(&(data_offset + 24).to_le_bytes());
+        tiff.extend(&[0, 0, 0, 0]);
+        tiff.extend(rational([(33, 1), (52, 1), (1800, 100)]));
+        tiff.extend(rational([(151, 1), (12, 1), (0, 1)]));
+
+        let exif = exif::Reader::new().read_raw(tiff).unwrap();
+        let (lat, lon) = PhotoMetadata::from_exif(&exif).location.unwrap();
+        assert!((lat - -33.8717).abs() < 0.0001);
+        assert!((lon - 151.2).abs() < 0.0001);
+    }
+
+    #[test]
+    fn reads_xmp_alt_text() {
+        let xmp = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF><rdf:Description>
+            <dc:description><rdf:Alt><rdf:li xml:lang="x-default">Fish &amp; chips &#233;</rdf:li></rdf:Alt></dc:description>
+            <dc:title><rdf:Alt><rdf:li xml:lang="x-default"></rdf:li></rdf:Alt></dc:title>
+            </rdf:Description></rdf:RDF></x:xmpmeta>"#;
+        assert_eq!(xmp_alt_text(xmp, "dc:description").as_deref(), Some("Fish & chips \u{e9}"));
+        assert_eq!(xmp_alt_text(xmp, "dc:title"), None);
+        assert_eq!(xmp_alt_text(xmp, "dc:rights"), None);
+    }
+
+    #[test]
+    fn reads_xmp_tags() {
+        let xmp = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF>
+            <rdf:Description xmp:Rating="4" xmp:Label="Red">
+            <dc:title><rdf:Alt><rdf:li xml:lang="x-default">Harbour</rdf:li></rdf:Alt></dc:title>
+            <dc:subject><rdf:Bag><rdf:li>boats</rdf:li><rdf:li>sea &amp; sky</rdf:li></rdf:Bag></dc:subject>
+            </rdf:Description></rdf:RDF></x:xmpmeta>"#;
+        let tags = PhotoTags::from_xmp(xmp);
+        assert_eq!(tags.rating, Some(4));
+        assert_eq!(tags.label.as_deref(), Some("Red"));
+        assert_eq!(tags.title.as_deref(), Some("Harbour"));
+        assert_eq!(tags.keywords, vec!["boats", "sea & sky"]);
+        assert_eq!(PhotoTags::from_xmp("<xmp:Rating>-1</xmp:Rating>").rating, Some(-1));
+        assert_eq!(tags.summary()[0], ("Rating", String::from("\u{2605}\u{2605}\u{2605}\u{2605}\u{2606}")));
+    }
+
+    #[test]
+    fn reads_embedded_iptc() {
+        let mut iim = Vec::new();
+        for (dataset, value) in [(5u8, "Harbour"), (25, "boats"), (25, "sea"), (120, "at dusk")].iter() {
+            iim.extend(&[0x1c, 2, *dataset]);
+            iim.extend(&(value.len() as u16).to_be_bytes());
+            iim.extend(value.as_bytes());
+        }
+        let mut head = b"\xff\xd8\xff\xedxxPhotoshop 3.0\x008BIM\x04\x04\x00\x00".to_vec();
+        head.extend(&(iim.len() as u32).to_be_bytes());
+        head.extend(&iim);
+        let tags = PhotoTags::from_iptc(embedded_iptc(&head).unwrap());
+        assert_eq!(tags.title.as_deref(), Some("Harbour"));
+        assert_eq!(tags.keywords, vec!["boats", "sea"]);
+        assert_eq!(tags.description.as_deref(), Some("at dusk"));
+        assert_eq!(tags.rating, None);
+    }
+}
@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+
+use super::feed::DEFAULT_FEED_AUTHOR;
+use super::geo::DEFAULT_TILE_URL;
+use super::html_generation::Layout;
+use super::thumbnail::ThumbnailSettings;
+use super::traversal::TraversalPolicy;
+
+// what a build writes besides the thumbnails
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum OutputTarget {
+    // an index.html in every directory, with the tag, timeline and detail pages
+    Html,
+    // a json manifest per album and one page at the top that draws them
+    Spa,
+}
+
+pub const OUTPUT_NAMES: [&str; 2] = ["html", "spa"];
+
+impl OutputTarget {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "html" => Some(OutputTarget::Html),
+            "spa"  => Some(OutputTarget::Spa),
+            _ => None,
+        }
+    }
+}
+
+// everything that controls how an album tree is built, gathered from the
+// command line in main
+#[derive(Clone,Debug)]
+pub struct BuildOptions {
+    pub max_depth:      usize,
+    pub resources_path: PathBuf,
+    pub make_local:     bool,
+    pub force_regen:    bool,
+    pub use_ffmpeg:     bool,
+    pub traversal:      TraversalPolicy,
+    pub include_globs:  Vec<String>,
+    pub exclude_globs:  Vec<String>,
+    pub thumbnails:     ThumbnailSettings,
+    pub output:         OutputTarget,
+    pub layout:         Layout,
+    // images per page, 0 for no limit
+    pub page_size:      usize,
+    // a page per photo, linked from its thumbnail
+    pub detail_pages:   bool,
+    // photos rated below this many stars are left out, unrated ones count as 0
+    pub min_rating:     Option<i8>,
+    // year, month and day pages alongside the folders
+    pub timeline:       bool,
+    // maps of where photos were taken, over tiles from `map_tiles` if it is
+    // set, credited with `map_attribution`
+    pub map:            bool,
+    pub map_tiles:      String,
+    pub map_attribution: Option<String>,
+    // an atom feed of recently changed albums, linked absolutely from `base_url`
+    // if it is set and credited to `feed_author`
+    pub feed:           bool,
+    pub base_url:       Option<String>,
+    pub feed_author:    String,
+}
+
+impl BuildOptions {
+    pub fn new(resources_path: PathBuf) -> Self {
+        Self {
+            max_depth:      5,
+            resources_path,
+            make_local:     false,
+            force_regen:    false,
+            use_ffmpeg:     false,
+            traversal:      TraversalPolicy::default(),
+            include_globs:  Vec::new(),
+            exclude_globs:  Vec::new(),
+            thumbnails:     ThumbnailSettings::default(),
+            output:         OutputTarget::Html,
+            layout:         Layout::Grid,
+            page_size:      0,
+            detail_pages:   false,
+            min_rating:     None,
+            timeline:       false,
+            map:            false,
+            map_tiles:      String::from(DEFAULT_TILE_URL),
+            map_attribution: None,
+            feed:           false,
+            base_url:       None,
+            feed_author:    String::from(DEFAULT_FEED_AUTHOR),
+        }
+    }
+}
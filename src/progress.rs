@@ -0,0 +1,247 @@
+use std::{
+    fs::File,
+    io::{self,BufWriter,IsTerminal,Write},
+    path::{Path,PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize,Ordering},
+    },
+    time::{Duration,Instant},
+};
+
+use log::{debug,error,warn};
+use serde::Serialize;
+
+// how often the status line is redrawn
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq,PartialOrd,Ord)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum RenderOutcome {
+    Rendered,
+    UpToDate,
+    Failed,
+}
+
+impl RenderOutcome {
+    // the outcome of a photo that needed several files rendering, failing if
+    // any of them did
+    pub fn combine(self, other: RenderOutcome) -> RenderOutcome {
+        match (self, other) {
+            (RenderOutcome::Failed, _) | (_, RenderOutcome::Failed) => RenderOutcome::Failed,
+            (RenderOutcome::Rendered, _) | (_, RenderOutcome::Rendered) => RenderOutcome::Rendered,
+            _ => RenderOutcome::UpToDate,
+        }
+    }
+}
+
+// tracks thumbnail rendering across all the rayon threads, drawing a single
+// status line on stderr rather than one line per file
+pub struct Progress {
+    draw_line:  bool,
+    start:      Instant,
+    last_draw:  Mutex<Instant>,
+    discovered: AtomicUsize,
+    rendered:   AtomicUsize,
+    up_to_date: AtomicUsize,
+    failed:     AtomicUsize,
+    failures:   Mutex<Vec<PathBuf>>,
+    // the indexes, maps and feeds beside the pages that couldn't be written
+    write_failed: AtomicUsize,
+    pages:      AtomicUsize,
+}
+
+impl Progress {
+    pub fn new(verbosity: Verbosity) -> Self {
+        let now = Instant::now();
+        Self {
+            // only animate a status line when someone is watching
+            draw_line:  verbosity == Verbosity::Normal && io::stderr().is_terminal(),
+            start:      now,
+            last_draw:  Mutex::new(now - REDRAW_INTERVAL),
+            discovered: AtomicUsize::new(0),
+            rendered:   AtomicUsize::new(0),
+            up_to_date: AtomicUsize::new(0),
+            failed:     AtomicUsize::new(0),
+            failures:   Mutex::new(Vec::new()),
+            write_failed: AtomicUsize::new(0),
+            pages:      AtomicUsize::new(0),
+        }
+    }
+
+    pub fn set_discovered(&self, count: usize) {
+        self.discovered.store(count, Ordering::SeqCst);
+    }
+
+    pub fn add_pages(&self, count: usize) {
+        self.pages.fetch_add(count, Ordering::SeqCst);
+    }
+
+    pub fn record(&self, file: &Path, outcome: RenderOutcome) {
+        match outcome {
+            RenderOutcome::Rendered => self.rendered.fetch_add(1, Ordering::SeqCst),
+            RenderOutcome::UpToDate => self.up_to_date.fetch_add(1, Ordering::SeqCst),
+            RenderOutcome::Failed   => {
+                self.failures.lock().unwrap().push(file.to_path_buf());
+                self.failed.fetch_add(1, Ordering::SeqCst)
+            },
+        };
+        match outcome {
+            RenderOutcome::Rendered => debug!("{:?} - rendered", file),
+            RenderOutcome::UpToDate => debug!("{:?} - up to date", file),
+            RenderOutcome::Failed   => warn!("{:?} - could not process", file),
+        }
+        self.draw(false);
+    }
+
+    pub fn record_write_failure(&self, file: &Path, reason: &io::Error) {
+        error!("{:?} - could not write: {}", file, reason);
+        self.failures.lock().unwrap().push(file.to_path_buf());
+        self.write_failed.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn processed(&self) -> usize {
+        self.rendered.load(Ordering::SeqCst)
+            + self.up_to_date.load(Ordering::SeqCst)
+            + self.failed.load(Ordering::SeqCst)
+    }
+
+    fn draw(&self, force: bool) {
+        if !self.draw_line {
+            return;
+        }
+        let mut last_draw = self.last_draw.lock().unwrap();
+        if !force && last_draw.elapsed() < REDRAW_INTERVAL {
+            return;
+        }
+        *last_draw = Instant::now();
+
+        let processed = self.processed();
+        let discovered = self.discovered.load(Ordering::SeqCst);
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let throughput = if elapsed > 0.0 { processed as f64/elapsed } else { 0.0 };
+        let eta = if throughput > 0.0 {
+            format_duration(discovered.saturating_sub(processed) as f64/throughput)
+        } else {
+            String::from("--")
+        };
+        eprint!("\r[{}/{}] rendered {}, up to date {}, failed {} | {:.1} files/s | ETA {}   ",
+            processed, discovered,
+            self.rendered.load(Ordering::SeqCst),
+            self.up_to_date.load(Ordering::SeqCst),
+            self.failed.load(Ordering::SeqCst),
+            throughput, eta);
+        io::stderr().flush().unwrap();
+    }
+
+    // stops the status line and summarises the run
+    pub fn finish(&self, albums: usize) -> BuildSummary {
+        if self.draw_line {
+            self.draw(true);
+            eprintln!();
+        }
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let processed = self.processed();
+        BuildSummary {
+            albums,
+            pages:      self.pages.load(Ordering::SeqCst),
+            discovered: self.discovered.load(Ordering::SeqCst),
+            rendered:   self.rendered.load(Ordering::SeqCst),
+            up_to_date: self.up_to_date.load(Ordering::SeqCst),
+            failed:     self.failed.load(Ordering::SeqCst),
+            write_failed: self.write_failed.load(Ordering::SeqCst),
+            elapsed_seconds: elapsed,
+            files_per_second: if elapsed > 0.0 { processed as f64/elapsed } else { 0.0 },
+            failures: self.failures.lock().unwrap().clone(),
+        }
+    }
+}
+
+#[derive(Debug,Serialize)]
+pub struct BuildSummary {
+    pub albums:           usize,
+    pub pages:            usize,
+    pub discovered:       usize,
+    pub rendered:         usize,
+    pub up_to_date:       usize,
+    pub failed:           usize,
+    pub write_failed:     usize,
+    pub elapsed_seconds:  f64,
+    pub files_per_second: f64,
+    pub failures:         Vec<PathBuf>,
+}
+
+impl BuildSummary {
+    pub fn print(&self) {
+        println!("Build summary");
+        println!("  {:<18}{}", "albums", self.albums);
+        println!("  {:<18}{}", "pages written", self.pages);
+        println!("  {:<18}{}", "files discovered", self.discovered);
+        println!("  {:<18}{}", "rendered", self.rendered);
+        println!("  {:<18}{}", "up to date", self.up_to_date);
+        println!("  {:<18}{}", "failed", self.failed);
+        if self.write_failed > 0 {
+            println!("  {:<18}{}", "writes failed", self.write_failed);
+        }
+        println!("  {:<18}{}", "elapsed", format_duration(self.elapsed_seconds));
+        println!("  {:<18}{:.1} files/s", "throughput", self.files_per_second);
+        for failure in self.failures.iter() {
+            println!("  failed: {:?}", failure);
+        }
+    }
+
+    pub fn write_json(&self, report_path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(report_path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()
+    }
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    if seconds >= 3600 {
+        format!("{}h{:02}m{:02}s", seconds/3600, (seconds%3600)/60, seconds%60)
+    } else if seconds >= 60 {
+        format!("{}m{:02}s", seconds/60, seconds%60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_outcomes() {
+        let progress = Progress::new(Verbosity::Quiet);
+        progress.set_discovered(3);
+        progress.record(Path::new("a.jpg"), RenderOutcome::Rendered);
+        progress.record(Path::new("b.jpg"), RenderOutcome::UpToDate);
+        progress.record(Path::new("c.jpg"), RenderOutcome::Failed);
+        progress.add_pages(1);
+        progress.record_write_failure(Path::new("feed.xml"), &io::Error::other("disk full"));
+        let summary = progress.finish(1);
+        assert_eq!(summary.discovered, 3);
+        assert_eq!(summary.pages, 1);
+        assert_eq!(summary.rendered, 1);
+        assert_eq!(summary.up_to_date, 1);
+        assert_eq!(summary.failed, 1);
+        assert_eq!(summary.write_failed, 1);
+        assert_eq!(summary.failures, vec![PathBuf::from("c.jpg"), PathBuf::from("feed.xml")]);
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(format_duration(5.2), "5s");
+        assert_eq!(format_duration(92.0), "1m32s");
+        assert_eq!(format_duration(3725.0), "1h02m05s");
+    }
+}
@@ -0,0 +1,309 @@
+use std::{
+    fs,
+    io::Cursor,
+    path::Path,
+};
+
+use image::{DynamicImage,ImageFormat,io::Reader};
+
+use super::media::{self,MediaKind};
+
+// tiff tags that point at embedded jpegs
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014A;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+const TAG_EXIF_IFD: u16 = 0x8769;
+// panasonic rw2 keeps its preview in a tag of its own
+const TAG_RW2_JPEG: u16 = 0x002E;
+
+// guards against malformed files sending the ifd walk round in circles
+const MAX_IFDS: usize = 32;
+// how many jpeg start markers the fallback scan will try
+const MAX_SCANNED_CANDIDATES: usize = 8;
+
+pub fn is_raw_file(path: &Path) -> bool {
+    matches!(media::identify_extension(path), Some((MediaKind::Raw, _)))
+}
+
+// decodes the largest jpeg preview embedded in a camera raw file, rotated as
+// the camera recorded it. No demosaicing is done
+pub fn open_preview(path: &Path) -> Result<DynamicImage, String> {
+    let data = fs::read(path).map_err(|reason| reason.to_string())?;
+    let (mut candidates, orientation) = find_previews(&data);
+    candidates.sort_by_key(|(_, length)| std::cmp::Reverse(*length));
+    for (offset, length) in candidates {
+        if let Some(image) = decode_jpeg(&data[offset..offset+length]) {
+            return Ok(orient(image, orientation));
+        }
+    }
+    // unrecognised layout, try anything that looks like the start of a jpeg
+    scan_for_jpegs(&data)
+        .map(|image| orient(image, orientation))
+        .ok_or_else(|| String::from("no embedded preview found"))
+}
+
+// returns the (offset, length) of every embedded jpeg the file's structure
+// points to, along with the image orientation if recorded
+pub fn find_previews(data: &[u8]) -> (Vec<(usize, usize)>, u16) {
+    // fujifilm raf has a fixed header pointing at its preview
+    if data.starts_with(b"FUJIFILMCCD-RAW") {
+        let offset = read_u32(data, 84, false).unwrap_or(0) as usize;
+        let length = read_u32(data, 88, false).unwrap_or(0) as usize;
+        let candidates = if is_jpeg_range(data, offset, length) { vec![(offset, length)] } else { Vec::new() };
+        return (candidates, 1);
+    }
+    match Tiff::new(data) {
+        Some(tiff) => tiff.previews(),
+        None => (Vec::new(), 1),
+    }
+}
+
+fn is_jpeg_range(data: &[u8], offset: usize, length: usize) -> bool {
+    length > 2 && offset.checked_add(length).map(|end| end <= data.len()).unwrap_or(false)
+        && data[offset..].starts_with(&[0xFF, 0xD8])
+}
+
+fn decode_jpeg(data: &[u8]) -> Option<DynamicImage> {
+    image::load_from_memory_with_format(data, ImageFormat::Jpeg).ok()
+}
+
+fn scan_for_jpegs(data: &[u8]) -> Option<DynamicImage> {
+    let starts = data.windows(3)
+        .enumerate()
+        .filter(|(_, window)| *window == [0xFF, 0xD8, 0xFF])
+        .map(|(offset, _)| offset)
+        .take(MAX_SCANNED_CANDIDATES);
+    // pick the biggest preview by its header before decoding anything
+    let (offset, _) = starts
+        .filter_map(|offset| {
+            let reader = Reader::with_format(Cursor::new(&data[offset..]), ImageFormat::Jpeg);
+            reader.into_dimensions().ok().map(|(width, height)| (offset, width as u64*height as u64))
+        })
+        .max_by_key(|(_, pixels)| *pixels)?;
+    decode_jpeg(&data[offset..])
+}
+
+fn orient(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        3 => image.rotate180(),
+        6 => image.rotate90(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize, little_endian: bool) -> Option<u16> {
+    let bytes = [*data.get(offset)?, *data.get(offset+1)?];
+    Some(if little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+}
+
+fn read_u32(data: &[u8], offset: usize, little_endian: bool) -> Option<u32> {
+    let bytes = [*data.get(offset)?, *data.get(offset+1)?, *data.get(offset+2)?, *data.get(offset+3)?];
+    Some(if little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+}
+
+// just enough of a tiff reader to follow the ifds found in raw files
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+struct IfdEntry {
+    tag: u16,
+    field_type: u16,
+    count: u32,
+    // where the value lives, inline in the entry or at an offset
+    value_offset: usize,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(0..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        Some(Self { data, little_endian })
+    }
+
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        read_u16(self.data, offset, self.little_endian)
+    }
+
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        read_u32(self.data, offset, self.little_endian)
+    }
+
+    fn entries(&self, ifd_offset: usize) -> (Vec<IfdEntry>, usize) {
+        let count = match self.u16_at(ifd_offset) {
+            Some(count) => count as usize,
+            None => return (Vec::new(), 0),
+        };
+        let mut entries = Vec::with_capacity(count);
+        for index in 0..count {
+            let entry_offset = ifd_offset + 2 + index*12;
+            let (tag, field_type, value_count) = match (self.u16_at(entry_offset), self.u16_at(entry_offset+2), self.u32_at(entry_offset+4)) {
+                (Some(tag), Some(field_type), Some(value_count)) => (tag, field_type, value_count),
+                _ => break,
+            };
+            let value_size = match field_type {
+                1 | 2 | 6 | 7 => 1,
+                3 | 8 => 2,
+                4 | 9 | 11 | 13 => 4,
+                _ => 8,
+            } * value_count as usize;
+            let value_offset = if value_size <= 4 {
+                entry_offset + 8
+            } else {
+                self.u32_at(entry_offset+8).unwrap_or(0) as usize
+            };
+            entries.push(IfdEntry { tag, field_type, count: value_count, value_offset });
+        }
+        let next = self.u32_at(ifd_offset + 2 + count*12).unwrap_or(0) as usize;
+        (entries, next)
+    }
+
+    // reads the index-th value of a short or long entry
+    fn value(&self, entry: &IfdEntry, index: usize) -> Option<usize> {
+        if index >= entry.count as usize {
+            return None;
+        }
+        match entry.field_type {
+            3 => self.u16_at(entry.value_offset + index*2).map(|value| value as usize),
+            4 | 13 => self.u32_at(entry.value_offset + index*4).map(|value| value as usize),
+            _ => None,
+        }
+    }
+
+    fn previews(&self) -> (Vec<(usize, usize)>, u16) {
+        let mut candidates = Vec::new();
+        let mut orientation = None;
+        let mut queue = vec![self.u32_at(4).unwrap_or(0) as usize];
+        let mut visited = Vec::new();
+
+        while let Some(ifd_offset) = queue.pop() {
+            if ifd_offset == 0 || visited.contains(&ifd_offset) || visited.len() >= MAX_IFDS {
+                continue;
+            }
+            visited.push(ifd_offset);
+            let (entries, next) = self.entries(ifd_offset);
+            queue.push(next);
+
+            let find = |tag: u16| entries.iter().find(|entry| entry.tag == tag);
+            if orientation.is_none() {
+                orientation = find(TAG_ORIENTATION).and_then(|entry| self.value(entry, 0)).map(|value| value as u16);
+            }
+            if let (Some(offset), Some(length)) = (find(TAG_JPEG_OFFSET), find(TAG_JPEG_LENGTH)) {
+                if let (Some(offset), Some(length)) = (self.value(offset, 0), self.value(length, 0)) {
+                    candidates.push((offset, length));
+                }
+            }
+            // previews stored as a single jpeg compressed strip
+            let compression = find(TAG_COMPRESSION).and_then(|entry| self.value(entry, 0));
+            if let (Some(6), Some(offsets), Some(lengths)) = (compression, find(TAG_STRIP_OFFSETS), find(TAG_STRIP_BYTE_COUNTS)) {
+                if offsets.count == 1 {
+                    if let (Some(offset), Some(length)) = (self.value(offsets, 0), self.value(lengths, 0)) {
+                        candidates.push((offset, length));
+                    }
+                }
+            }
+            if let Some(entry) = find(TAG_RW2_JPEG) {
+                candidates.push((entry.value_offset, entry.count as usize));
+            }
+            for tag in [TAG_SUB_IFDS, TAG_EXIF_IFD].iter() {
+                if let Some(entry) = find(*tag) {
+                    for index in 0..entry.count as usize {
+                        if let Some(offset) = self.value(entry, index) {
+                            queue.push(offset);
+                        }
+                    }
+                }
+            }
+        }
+        candidates.retain(|(offset, length)| is_jpeg_range(self.data, *offset, *length));
+        (candidates, orientation.unwrap_or(1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::{GenericImageView,RgbImage,Rgb,codecs::jpeg::JpegEncoder};
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        JpegEncoder::new(&mut bytes)
+            .encode_image(&DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb([10, 200, 10]))))
+            .unwrap();
+        bytes
+    }
+
+    // a little endian tiff with a small jpeg referenced from ifd0, and a bigger
+    // one referenced from a sub ifd, as nikon and sony lay their files out
+    fn fake_raw(orientation: u16) -> Vec<u8> {
+        let small = jpeg(16, 8);
+        let large = jpeg(64, 32);
+        let ifd0 = 8;
+        let sub_ifd = ifd0 + 2 + 4*12 + 4;
+        let small_offset = sub_ifd + 2 + 3*12 + 4;
+        let large_offset = small_offset + small.len();
+
+        let mut data = b"II*\0".to_vec();
+        data.extend_from_slice(&(ifd0 as u32).to_le_bytes());
+        let entry = |data: &mut Vec<u8>, tag: u16, field_type: u16, value: u32| {
+            data.extend_from_slice(&tag.to_le_bytes());
+            data.extend_from_slice(&field_type.to_le_bytes());
+            data.extend_from_slice(&1u32.to_le_bytes());
+            data.extend_from_slice(&value.to_le_bytes());
+        };
+        data.extend_from_slice(&4u16.to_le_bytes());
+        entry(&mut data, TAG_ORIENTATION, 3, orientation as u32);
+        entry(&mut data, TAG_SUB_IFDS, 4, sub_ifd as u32);
+        entry(&mut data, TAG_JPEG_OFFSET, 4, small_offset as u32);
+        entry(&mut data, TAG_JPEG_LENGTH, 4, small.len() as u32);
+        data.extend_from_slice(&0u32.to_le_bytes());
+
+        data.extend_from_slice(&3u16.to_le_bytes());
+        entry(&mut data, TAG_COMPRESSION, 3, 6);
+        entry(&mut data, TAG_STRIP_OFFSETS, 4, large_offset as u32);
+        entry(&mut data, TAG_STRIP_BYTE_COUNTS, 4, large.len() as u32);
+        data.extend_from_slice(&0u32.to_le_bytes());
+
+        data.extend_from_slice(&small);
+        data.extend_from_slice(&large);
+        data
+    }
+
+    #[test]
+    fn finds_embedded_previews() {
+        let data = fake_raw(1);
+        let (candidates, orientation) = find_previews(&data);
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(orientation, 1);
+    }
+
+    #[test]
+    fn extracts_largest_preview_rotated() {
+        let dir = std::path::PathBuf::from("./test_files_out/raw");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("DSC_0001.NEF");
+        fs::write(&path, fake_raw(6)).unwrap();
+        let preview = open_preview(&path).unwrap();
+        // 64x32 rotated a quarter turn
+        assert_eq!((preview.width(), preview.height()), (32, 64));
+        assert!(is_raw_file(&path));
+    }
+
+    #[test]
+    fn falls_back_to_scanning() {
+        let mut data = b"not a tiff at all".to_vec();
+        data.extend_from_slice(&jpeg(16, 8));
+        data.extend_from_slice(&jpeg(48, 24));
+        let preview = scan_for_jpegs(&data).unwrap();
+        assert_eq!(preview.width(), 48);
+    }
+}
@@ -0,0 +1,109 @@
+use std::{
+    fs,
+    io,
+    path::PathBuf,
+};
+
+use serde::Serialize;
+
+use super::html_generation::detail_page_path;
+use super::timeline;
+use super::util::{ActionRecord,PhotoAction,relative};
+
+// loaded as a script rather than fetched, so searching works over file://
+pub const SEARCH_INDEX_FILE_NAME: &str = "search_index.js";
+
+// everything main.js searches, with paths relative to the top of the tree.
+// Photos are arrays rather than objects to keep the file small
+#[derive(Debug,Serialize)]
+pub struct SearchIndex {
+    // the path of each album, "" for the top one
+    pub albums: Vec<String>,
+    pub photos: Vec<SearchEntry>,
+}
+
+// name, index of its album, link, thumbnail, caption or title, tags and the
+// day it was taken
+#[derive(Debug,Serialize)]
+pub struct SearchEntry(String, usize, String, String, String, Vec<String>, String);
+
+impl SearchIndex {
+    pub fn new(root: &ActionRecord, detail_pages: bool) -> Self {
+        let root_path = root.get_path();
+        let mut albums = Vec::new();
+        let mut photos = Vec::new();
+        for ar in root.get_all_records() {
+            let album = albums.len();
+            albums.push(relative(&root_path, &ar.get_path()));
+            for pa in ar.get_photos() {
+                let link = if detail_pages { detail_page_path(pa) } else { pa.get_actual() };
+                let tags = pa.get_tags();
+                let text = [tags.title.as_deref(), pa.get_caption()].iter()
+                    .flatten()
+                    .copied()
+                    .collect::<Vec<&str>>()
+                    .join(" - ");
+                let date = timeline::capture_time(pa)
+                    .map(|time| time.format("%Y-%m-%d").to_string())
+                    .unwrap_or_default();
+                photos.push(SearchEntry(
+                    pa.get_name().unwrap_or_default().to_owned(),
+                    album,
+                    relative(&root_path, &in_album(pa, link)),
+                    relative(&root_path, &in_album(pa, pa.get_downsized())),
+                    text,
+                    tags.keywords.clone(),
+                    date));
+            }
+        }
+        Self { albums, photos }
+    }
+
+    // writes the index to the top of the tree as a script setting a global.
+    // Serialised before the file is created, so nothing is left half written
+    pub fn write(&self, root: &ActionRecord) -> io::Result<()> {
+        let script = format!("window.ALBUM_SEARCH_INDEX = {};\n", serde_json::to_string(self)?);
+        fs::write(search_index_path(root), script)
+    }
+}
+
+pub fn search_index_path(root: &ActionRecord) -> PathBuf {
+    root.get_path().join(SEARCH_INDEX_FILE_NAME)
+}
+
+// removes the index of the last build, for when this one has no search box
+pub fn clear_search_index(root: &ActionRecord) {
+    let search_index_path = search_index_path(root);
+    if search_index_path.exists() {
+        fs::remove_file(&search_index_path).unwrap();
+    }
+}
+
+fn in_album(pa: &PhotoAction, path: PathBuf) -> PathBuf {
+    pa.get_dir().join(path)
+}
+
+// the top of the tree as seen from a page `depth` directories below it
+pub fn root_dir(depth: usize) -> String {
+    if depth == 0 {
+        String::from(".")
+    } else {
+        vec![".."; depth].join("/")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::test::album_fixture;
+
+    #[test]
+    fn indexes_the_whole_tree() {
+        let index = SearchIndex::new(&album_fixture(), true);
+        assert_eq!(index.albums, vec!["", "trip"]);
+        assert_eq!(serde_json::to_string(&index.photos).unwrap(),
+            r#"[["boat.jpg",1,"trip/cacheDir/boat.jpg.html","trip/cacheDir/boat.jpg.500w.jpg","Harbour - at dusk",["sea"],""]]"#);
+        assert_eq!(root_dir(0), ".");
+        assert_eq!(root_dir(2), "../..");
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::BTreeMap;
+
+use super::util::{ActionRecord,PhotoAction};
+
+// where the page for each tag is written, in the cacheDir of the top directory
+// of the tree so it can't be mistaken for an album of the same name
+pub const TAGS_DIR_NAME: &str = "tags";
+
+// a keyword and every photo in the tree tagged with it
+pub struct Tag<'a> {
+    // as first seen, tags differing only in case or punctuation are merged
+    pub name:   String,
+    pub photos: Vec<&'a PhotoAction>,
+}
+
+impl<'a> Tag<'a> {
+    pub fn file_name(&self) -> String {
+        tag_file_name(&self.name)
+    }
+}
+
+// gathers the keywords of every photo in the tree, sorted by name. Photos are
+// in the order they appear in the tree
+pub fn collect_tags(root: &ActionRecord) -> Vec<Tag<'_>> {
+    let mut tags : BTreeMap<String, Tag> = BTreeMap::new();
+    for pa in root.get_all_photos() {
+        for keyword in pa.get_tags().keywords.iter() {
+            let tag = tags.entry(tag_slug(keyword))
+                .or_insert_with(|| Tag { name: keyword.clone(), photos: Vec::new() });
+            tag.photos.push(pa);
+        }
+    }
+    tags.into_values().collect()
+}
+
+pub fn tag_file_name(name: &str) -> String {
+    format!("{}.html", tag_slug(name))
+}
+
+// lower case letters and digits, with runs of anything else made a single dash
+fn tag_slug(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.trim().chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        String::from("-")
+    } else {
+        slug.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::{Path,PathBuf};
+    use crate::media::MediaKind;
+    use crate::metadata::PhotoTags;
+    use crate::util::test::album_fixture;
+
+    fn tagged(root: &Path, name: &str, keywords: &[&str]) -> PhotoAction {
+        let mut pa = PhotoAction::new(root.to_path_buf(), PathBuf::from(name), PathBuf::from(format!("cacheDir/{}", name)), MediaKind::Image);
+        pa.set_tags(PhotoTags {
+            keywords: keywords.iter().map(|keyword| keyword.to_string()).collect(),
+            ..PhotoTags::default()
+        });
+        pa
+    }
+
+    #[test]
+    fn gathers_tags_across_the_tree() {
+        let mut root = album_fixture();
+        let root_path = root.get_path();
+        root.add_photo_action(tagged(&root_path, "a.jpg", &["Sea", "boats"]));
+
+        // the spelling seen first names the tag, the top album before `trip`
+        let tags = collect_tags(&root);
+        let names : Vec<&str> = tags.iter().map(|tag| tag.name.as_str()).collect();
+        assert_eq!(names, vec!["boats", "Sea"]);
+        assert_eq!(tags[1].photos.len(), 2);
+        assert_eq!(tags[1].file_name(), "sea.html");
+        assert_eq!(tag_file_name("  Café / Bar! "), "café-bar.html");
+        assert_eq!(tag_file_name("??"), "-.html");
+    }
+}
@@ -0,0 +1,631 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self,File},
+    io::BufWriter,
+    path::{Path,PathBuf},
+    sync::OnceLock,
+};
+
+use image::{DynamicImage,GenericImageView,imageops::FilterType};
+use regex::Regex;
+use serde::{Deserialize,Serialize};
+
+use super::media::MediaKind;
+use super::util::get_cache_dir_path;
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum ThumbnailFormat {
+    Jpeg,
+    Png,
+    WebP,
+    Avif,
+}
+
+pub const ALL_THUMBNAIL_FORMATS: [ThumbnailFormat; 4] = [
+    ThumbnailFormat::Jpeg, ThumbnailFormat::Png, ThumbnailFormat::WebP, ThumbnailFormat::Avif,
+];
+
+impl ThumbnailFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "jpeg" | "jpg" => Some(ThumbnailFormat::Jpeg),
+            "png"  => Some(ThumbnailFormat::Png),
+            "webp" => Some(ThumbnailFormat::WebP),
+            "avif" => Some(ThumbnailFormat::Avif),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ThumbnailFormat::Jpeg => "jpeg",
+            ThumbnailFormat::Png  => "png",
+            ThumbnailFormat::WebP => "webp",
+            ThumbnailFormat::Avif => "avif",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ThumbnailFormat::Jpeg => "jpg",
+            _ => self.name(),
+        }
+    }
+
+    // whether this build has an encoder for the format
+    pub fn encodable(&self) -> bool {
+        match self {
+            ThumbnailFormat::Jpeg | ThumbnailFormat::Png => true,
+            ThumbnailFormat::WebP => cfg!(feature = "webp-thumbnails"),
+            ThumbnailFormat::Avif => cfg!(feature = "avif-thumbnails"),
+        }
+    }
+
+    pub fn capability(&self) -> &'static str {
+        match self {
+            ThumbnailFormat::Jpeg => "built in, progressive",
+            ThumbnailFormat::Png  => "built in, lossless",
+            ThumbnailFormat::WebP if self.encodable() => "enabled",
+            ThumbnailFormat::WebP => "not compiled in, rebuild with the webp-thumbnails cargo feature",
+            ThumbnailFormat::Avif if self.encodable() => "enabled, slow to encode",
+            ThumbnailFormat::Avif => "not compiled in, rebuild with the avif-thumbnails cargo feature",
+        }
+    }
+}
+
+pub const FILTER_NAMES: [&str; 5] = ["nearest", "triangle", "catmullrom", "gaussian", "lanczos3"];
+
+pub fn filter_name(filter: FilterType) -> &'static str {
+    match filter {
+        FilterType::Nearest    => "nearest",
+        FilterType::Triangle   => "triangle",
+        FilterType::CatmullRom => "catmullrom",
+        FilterType::Gaussian   => "gaussian",
+        FilterType::Lanczos3   => "lanczos3",
+    }
+}
+
+pub fn parse_filter(name: &str) -> Option<FilterType> {
+    match name.to_lowercase().as_str() {
+        "nearest"    => Some(FilterType::Nearest),
+        "triangle"   => Some(FilterType::Triangle),
+        "catmullrom" => Some(FilterType::CatmullRom),
+        "gaussian"   => Some(FilterType::Gaussian),
+        "lanczos3"   => Some(FilterType::Lanczos3),
+        _ => None,
+    }
+}
+
+// how a photo is fitted to the thumbnail size
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThumbnailMode {
+    // exactly `size` wide
+    Width,
+    // exactly `size` tall
+    Height,
+    // cropped to a `size` square
+    Square,
+    // within a `size` square, uncropped
+    Fit,
+}
+
+pub const MODE_NAMES: [&str; 4] = ["width", "height", "square", "fit"];
+
+impl ThumbnailMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "width"  => Some(ThumbnailMode::Width),
+            "height" => Some(ThumbnailMode::Height),
+            "square" => Some(ThumbnailMode::Square),
+            "fit"    => Some(ThumbnailMode::Fit),
+            _ => None,
+        }
+    }
+}
+
+// which part of a photo survives a square crop
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CropAnchor {
+    Center,
+    Top,
+    Bottom,
+    Left,
+    Right,
+    // wherever there is the most detail
+    Smart,
+}
+
+pub const ANCHOR_NAMES: [&str; 6] = ["center", "top", "bottom", "left", "right", "smart"];
+
+impl CropAnchor {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "center" | "centre" => Some(CropAnchor::Center),
+            "top"    => Some(CropAnchor::Top),
+            "bottom" => Some(CropAnchor::Bottom),
+            "left"   => Some(CropAnchor::Left),
+            "right"  => Some(CropAnchor::Right),
+            "smart"  => Some(CropAnchor::Smart),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CropAnchor::Center => "center",
+            CropAnchor::Top    => "top",
+            CropAnchor::Bottom => "bottom",
+            CropAnchor::Left   => "left",
+            CropAnchor::Right  => "right",
+            CropAnchor::Smart  => "smart",
+        }
+    }
+
+    // where a crop starts along one axis, given how much has to be cut off
+    fn offset(&self, slack: u32, horizontal: bool) -> u32 {
+        match (self, horizontal) {
+            (CropAnchor::Left, true) | (CropAnchor::Top, false) => 0,
+            (CropAnchor::Right, true) | (CropAnchor::Bottom, false) => slack,
+            _ => slack/2,
+        }
+    }
+}
+
+// small changes in brightness are left alone by the unsharp mask, so noise
+// doesn't get sharpened along with edges
+const SHARPEN_THRESHOLD: i32 = 2;
+
+// the longest side of the copy shown on a photo's detail page
+pub const WEB_RENDITION_SIZE: u32 = 1600;
+
+// how thumbnails are sized and encoded
+#[derive(Clone,Copy,Debug)]
+pub struct ThumbnailSettings {
+    // the width, height or side length, depending on the mode
+    pub size:      u32,
+    pub mode:      ThumbnailMode,
+    pub anchor:    CropAnchor,
+    pub format:    ThumbnailFormat,
+    // 1 to 100, ignored by png
+    pub quality:   u8,
+    pub filter:    FilterType,
+    // sigma of an unsharp mask applied after resizing
+    pub sharpen:   Option<f32>,
+    // decode jpegs at a reduced scale before resizing
+    pub fast_jpeg: bool,
+}
+
+impl Default for ThumbnailSettings {
+    fn default() -> Self {
+        Self {
+            size:      500,
+            mode:      ThumbnailMode::Width,
+            anchor:    CropAnchor::Center,
+            format:    ThumbnailFormat::Jpeg,
+            quality:   85,
+            filter:    FilterType::Triangle,
+            sharpen:   None,
+            fast_jpeg: true,
+        }
+    }
+}
+
+impl ThumbnailSettings {
+    // the extension a thumbnail of a source of `kind` will be saved with.
+    // Animated gifs stay gifs
+    pub fn extension_for(&self, kind: MediaKind, animated: bool) -> &'static str {
+        match kind {
+            MediaKind::Image if cfg!(feature = "gif") && animated => "gif",
+            _ => self.format.extension(),
+        }
+    }
+
+    // where the thumbnail for a source file is cached. The name carries every
+    // setting that changes the output, so thumbnails made with different
+    // settings can coexist and changing one is never served a stale thumbnail
+    pub fn thumbnail_path(&self, original_path: &Path, cache_dir_name: &str, kind: MediaKind, animated: bool) -> PathBuf {
+        let cache_path = get_cache_dir_path(original_path, cache_dir_name);
+        let file_name = format!("{}.{}.{}.{}",
+            cache_path.file_name().unwrap().to_string_lossy(),
+            self.size_tag(),
+            self.encoding_tag(),
+            self.extension_for(kind, animated));
+        cache_path.with_file_name(file_name)
+    }
+
+    fn size_tag(&self) -> String {
+        match self.mode {
+            ThumbnailMode::Width  => format!("{}w", self.size),
+            ThumbnailMode::Height => format!("{}h", self.size),
+            ThumbnailMode::Fit    => format!("{}fit", self.size),
+            ThumbnailMode::Square if self.anchor == CropAnchor::Center => format!("{}sq", self.size),
+            ThumbnailMode::Square => format!("{}sq-{}", self.size, self.anchor.name()),
+        }
+    }
+
+    // e.g. q85-triangle, or q85-lanczos3-s1.5 when sharpened
+    fn encoding_tag(&self) -> String {
+        match self.sharpen {
+            Some(sigma) if sigma > 0.0 => format!("q{}-{}-s{}", self.quality, filter_name(self.filter), sigma),
+            _ => format!("q{}-{}", self.quality, filter_name(self.filter)),
+        }
+    }
+
+    // the larger, uncropped copy shown on a photo's detail page
+    pub fn web_rendition(&self) -> Self {
+        Self {
+            size:    WEB_RENDITION_SIZE,
+            mode:    ThumbnailMode::Fit,
+            anchor:  CropAnchor::Center,
+            sharpen: None,
+            ..*self
+        }
+    }
+
+    // the size a `width` by `height` photo is scaled to, before any cropping
+    pub fn scaled_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let (width, height) = (width.max(1) as f64, height.max(1) as f64);
+        let size = self.size as f64;
+        let scale = match self.mode {
+            ThumbnailMode::Width  => size/width,
+            ThumbnailMode::Height => size/height,
+            ThumbnailMode::Fit    => (size/width).min(size/height),
+            ThumbnailMode::Square => (size/width).max(size/height),
+        };
+        (((width*scale).round() as u32).max(1), ((height*scale).round() as u32).max(1))
+    }
+
+    // the x, y, width and height of the part of a scaled photo that is kept
+    pub fn crop_box(&self, image: &DynamicImage) -> (u32, u32, u32, u32) {
+        let (width, height) = image.dimensions();
+        if self.mode != ThumbnailMode::Square {
+            return (0, 0, width, height);
+        }
+        let side = width.min(height);
+        let (x, y) = match self.anchor {
+            CropAnchor::Smart => smart_crop_offset(image, side),
+            anchor => (anchor.offset(width-side, true), anchor.offset(height-side, false)),
+        };
+        (x, y, side, side)
+    }
+}
+
+// slides a `side` long window along the long edge of the image to wherever it
+// takes in the most detail, measured as the sum of brightness gradients. Flat
+// areas like sky and walls score low, so the subject tends to be kept
+fn smart_crop_offset(image: &DynamicImage, side: u32) -> (u32, u32) {
+    let luma = image.to_luma8();
+    let (width, height) = luma.dimensions();
+    let horizontal = width > height;
+    let length = if horizontal { width } else { height } as usize;
+    let side = side as usize;
+    if length <= side {
+        return (0, 0);
+    }
+
+    // detail in each column, or each row for portrait photos
+    let mut energy = vec![0u64; length];
+    for y in 0..height.saturating_sub(1) {
+        for x in 0..width.saturating_sub(1) {
+            let here = luma.get_pixel(x, y)[0] as i32;
+            let gradient = (luma.get_pixel(x+1, y)[0] as i32 - here).abs()
+                + (luma.get_pixel(x, y+1)[0] as i32 - here).abs();
+            energy[if horizontal { x } else { y } as usize] += gradient as u64;
+        }
+    }
+
+    // best window, preferring the middle when several are equally good
+    let centre = (length-side)/2;
+    let distance = |start: usize| (start as i64 - centre as i64).abs();
+    let mut window : u64 = energy[..side].iter().sum();
+    let (mut best, mut best_start) = (window, 0);
+    for start in 1..=(length-side) {
+        window = window + energy[start+side-1] - energy[start-1];
+        if window > best || (window == best && distance(start) < distance(best_start)) {
+            best = window;
+            best_start = start;
+        }
+    }
+    if horizontal { (best_start as u32, 0) } else { (0, best_start as u32) }
+}
+
+// scales and crops an image as the settings ask, sharpening the result if
+// asked to
+pub fn render(image: &DynamicImage, settings: &ThumbnailSettings) -> DynamicImage {
+    let (width, height) = settings.scaled_size(image.width(), image.height());
+    let resized = image.resize_exact(width, height, settings.filter);
+    let (x, y, crop_width, crop_height) = settings.crop_box(&resized);
+    let cropped = if (crop_width, crop_height) == (width, height) {
+        resized
+    } else {
+        resized.crop_imm(x, y, crop_width, crop_height)
+    };
+    match settings.sharpen {
+        Some(sigma) if sigma > 0.0 => cropped.unsharpen(sigma, SHARPEN_THRESHOLD),
+        _ => cropped,
+    }
+}
+
+// width of the tiny image inlined into pages while a thumbnail loads
+const PLACEHOLDER_WIDTH: u32 = 16;
+
+// a blurred stand in for a thumbnail, small enough to inline into the page
+#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
+pub struct Placeholder {
+    // the average colour, shown until even the placeholder has decoded
+    pub colour:   [u8; 3],
+    pub data_uri: String,
+}
+
+pub fn placeholder(thumbnail: &DynamicImage) -> Option<Placeholder> {
+    use image::codecs::jpeg::JpegEncoder;
+
+    let height = (thumbnail.height() as u64*PLACEHOLDER_WIDTH as u64/thumbnail.width().max(1) as u64).max(1) as u32;
+    let tiny = thumbnail.resize_exact(PLACEHOLDER_WIDTH, height, FilterType::Triangle)
+        .blur(1.0)
+        .to_rgb8();
+
+    let pixel_count = (tiny.width()*tiny.height()).max(1) as u64;
+    let mut totals = [0u64; 3];
+    for pixel in tiny.pixels() {
+        for channel in 0..3 {
+            totals[channel] += pixel[channel] as u64;
+        }
+    }
+    let colour = [(totals[0]/pixel_count) as u8, (totals[1]/pixel_count) as u8, (totals[2]/pixel_count) as u8];
+
+    let mut bytes = Vec::new();
+    JpegEncoder::new_with_quality(&mut bytes, 40).encode_image(&tiny).ok()?;
+    Some(Placeholder {
+        colour,
+        data_uri: format!("data:image/jpeg;base64,{}", base64::encode(&bytes)),
+    })
+}
+
+// kept in each cacheDir, so thumbnails that are already up to date needn't be
+// decoded again to lay out the pages
+pub const THUMBNAIL_INFO_FILE_NAME: &str = "thumbnails.json";
+
+// what the pages need to know about a rendered thumbnail
+#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
+pub struct ThumbnailInfo {
+    pub size:        (u32, u32),
+    pub placeholder: Option<Placeholder>,
+}
+
+pub fn describe(thumbnail: &DynamicImage) -> ThumbnailInfo {
+    ThumbnailInfo {
+        size:        thumbnail.dimensions(),
+        placeholder: placeholder(thumbnail),
+    }
+}
+
+// the ThumbnailInfo of every thumbnail in a cacheDir, by file name
+#[derive(Debug,Default,Serialize,Deserialize)]
+pub struct ThumbnailInfoCache(BTreeMap<String, ThumbnailInfo>);
+
+impl ThumbnailInfoCache {
+    // a missing or unreadable file gives an empty cache
+    pub fn load(cache_dir: &Path) -> Self {
+        fs::read(cache_dir.join(THUMBNAIL_INFO_FILE_NAME)).ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, cache_dir: &Path) -> Result<(), String> {
+        let bytes = serde_json::to_vec(self).map_err(|reason| reason.to_string())?;
+        fs::write(cache_dir.join(THUMBNAIL_INFO_FILE_NAME), bytes).map_err(|reason| reason.to_string())
+    }
+
+    pub fn get(&self, thumbnail_name: &str) -> Option<&ThumbnailInfo> {
+        self.0.get(thumbnail_name)
+    }
+
+    pub fn insert(&mut self, thumbnail_name: String, info: ThumbnailInfo) {
+        self.0.insert(thumbnail_name, info);
+    }
+}
+
+// undoes ThumbnailSettings::thumbnail_path, giving the name of the file a
+// thumbnail was made from
+pub fn source_name_for_thumbnail(thumbnail_name: &str) -> String {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| Regex::new(r"^(.+)\.\d+(w|h|fit|sq|sq-[a-z]+)(\.q\d+-[a-z0-9]+(-s[0-9.]+)?)?\.(jpg|png|webp|avif|gif)$").unwrap());
+    match pattern.captures(thumbnail_name) {
+        Some(captures) => captures[1].to_owned(),
+        // thumbnails from older versions were named after their source
+        None => thumbnail_name.to_owned(),
+    }
+}
+
+// writes `image` out in the format given by the extension of `out_file`. Only
+// pixels are encoded, so no exif or other metadata from the source survives
+pub fn encode(image: &DynamicImage, out_file: &Path, settings: &ThumbnailSettings) -> Result<(), String> {
+    let extension = out_file.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+    match ThumbnailFormat::from_name(extension) {
+        Some(ThumbnailFormat::Jpeg) => encode_jpeg(image, out_file, settings.quality),
+        Some(ThumbnailFormat::WebP) => encode_webp(image, out_file, settings.quality),
+        Some(ThumbnailFormat::Avif) => encode_avif(image, out_file, settings.quality),
+        // png and gif are written by the image crate
+        _ => image.save(out_file).map_err(|reason| reason.to_string()),
+    }
+}
+
+fn encode_jpeg(image: &DynamicImage, out_file: &Path, quality: u8) -> Result<(), String> {
+    use jpeg_encoder::{ColorType,Encoder};
+
+    let (width, height) = image.dimensions();
+    if width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(String::from("too large for a jpeg"));
+    }
+    let pixels = image.to_rgb8();
+    let writer = BufWriter::new(File::create(out_file).map_err(|reason| reason.to_string())?);
+    let mut encoder = Encoder::new(writer, quality);
+    encoder.set_progressive(true);
+    encoder.encode(&pixels, width as u16, height as u16, ColorType::Rgb)
+        .map_err(|reason| reason.to_string())
+}
+
+#[cfg(feature = "webp-thumbnails")]
+fn encode_webp(image: &DynamicImage, out_file: &Path, quality: u8) -> Result<(), String> {
+    let (width, height) = image.dimensions();
+    let pixels = image.to_rgba8();
+    let encoded = webp_encoder::Encoder::from_rgba(&pixels, width, height).encode(quality as f32);
+    std::fs::write(out_file, &*encoded).map_err(|reason| reason.to_string())
+}
+
+#[cfg(not(feature = "webp-thumbnails"))]
+fn encode_webp(_image: &DynamicImage, _out_file: &Path, _quality: u8) -> Result<(), String> {
+    Err(String::from("no webp encoder in this build"))
+}
+
+#[cfg(feature = "avif-thumbnails")]
+fn encode_avif(image: &DynamicImage, out_file: &Path, quality: u8) -> Result<(), String> {
+    use ravif::{Encoder,Img,RGB8};
+
+    let (width, height) = image.dimensions();
+    let pixels : Vec<RGB8> = image.to_rgb8().pixels()
+        .map(|pixel| RGB8::new(pixel[0], pixel[1], pixel[2]))
+        .collect();
+    let encoded = Encoder::new()
+        .with_quality(quality as f32)
+        .with_speed(8)
+        .encode_rgb(Img::new(&pixels[..], width as usize, height as usize))
+        .map_err(|reason| reason.to_string())?;
+    std::fs::write(out_file, encoded.avif_file).map_err(|reason| reason.to_string())
+}
+
+#[cfg(not(feature = "avif-thumbnails"))]
+fn encode_avif(_image: &DynamicImage, _out_file: &Path, _quality: u8) -> Result<(), String> {
+    Err(String::from("no avif encoder in this build"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use image::{Rgb,RgbImage};
+
+    #[test]
+    fn names_thumbnails_after_settings() {
+        let settings = ThumbnailSettings { size: 320, format: ThumbnailFormat::WebP, ..ThumbnailSettings::default() };
+        let source = PathBuf::from("shot.PNG");
+        let thumbnail = settings.thumbnail_path(&source, "cacheDir", MediaKind::Image, false);
+        assert_eq!(thumbnail, PathBuf::from("cacheDir/shot.PNG.320w.q85-triangle.webp"));
+        let video = PathBuf::from("clip.mov");
+        assert_eq!(settings.thumbnail_path(&video, "cacheDir", MediaKind::Video, false),
+            PathBuf::from("cacheDir/clip.mov.320w.q85-triangle.webp"));
+
+        // changing how a thumbnail is encoded changes its name
+        let sharper = ThumbnailSettings { quality: 70, filter: FilterType::Lanczos3, sharpen: Some(1.5), ..settings };
+        let thumbnail = sharper.thumbnail_path(&source, "cacheDir", MediaKind::Image, false);
+        assert_eq!(thumbnail, PathBuf::from("cacheDir/shot.PNG.320w.q70-lanczos3-s1.5.webp"));
+        assert_eq!(source_name_for_thumbnail("shot.PNG.320w.q70-lanczos3-s1.5.webp"), "shot.PNG");
+
+        assert_eq!(source_name_for_thumbnail("shot.PNG.320w.q85-triangle.webp"), "shot.PNG");
+        assert_eq!(source_name_for_thumbnail("IMG_0001.CR2.500w.q85-catmullrom.jpg"), "IMG_0001.CR2");
+        // thumbnails named before the encoding settings were
+        assert_eq!(source_name_for_thumbnail("IMG_0001.CR2.500w.jpg"), "IMG_0001.CR2");
+        assert_eq!(source_name_for_thumbnail("old.jpg"), "old.jpg");
+
+        let square = ThumbnailSettings { mode: ThumbnailMode::Square, anchor: CropAnchor::Smart, ..settings };
+        let thumbnail = square.thumbnail_path(&source, "cacheDir", MediaKind::Image, false);
+        assert_eq!(thumbnail, PathBuf::from("cacheDir/shot.PNG.320sq-smart.q85-triangle.webp"));
+        assert_eq!(source_name_for_thumbnail("shot.PNG.320sq-smart.q85-triangle.webp"), "shot.PNG");
+
+        // only animated gifs keep their format, and never for the detail page copy
+        let gif = PathBuf::from("wave.gif");
+        assert_eq!(settings.thumbnail_path(&gif, "cacheDir", MediaKind::Image, false),
+            PathBuf::from("cacheDir/wave.gif.320w.q85-triangle.webp"));
+        if cfg!(feature = "gif") {
+            assert_eq!(settings.thumbnail_path(&gif, "cacheDir", MediaKind::Image, true),
+                PathBuf::from("cacheDir/wave.gif.320w.q85-triangle.gif"));
+        }
+    }
+
+    #[test]
+    fn renders_each_mode() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(800, 400, Rgb([50, 50, 50])));
+        let cases = [
+            (ThumbnailMode::Width, (200, 100)),
+            (ThumbnailMode::Height, (400, 200)),
+            (ThumbnailMode::Square, (200, 200)),
+            (ThumbnailMode::Fit, (200, 100)),
+        ];
+        for (mode, expected) in cases.iter() {
+            let settings = ThumbnailSettings { size: 200, mode: *mode, ..ThumbnailSettings::default() };
+            assert_eq!(render(&image, &settings).dimensions(), *expected, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn smart_crop_finds_detail() {
+        // a flat photo with a checkered patch towards its right hand edge
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(300, 100, |x, y| {
+            if (220..280).contains(&x) && (x/4 + y/4) % 2 == 0 { Rgb([255, 255, 255]) } else { Rgb([40, 40, 40]) }
+        }));
+        let smart = ThumbnailSettings { mode: ThumbnailMode::Square, anchor: CropAnchor::Smart, ..ThumbnailSettings::default() };
+        let (x, _, side, _) = smart.crop_box(&image);
+        assert!(x <= 220 && x+side >= 280, "{}", x);
+
+        let flat = DynamicImage::ImageRgb8(RgbImage::from_pixel(300, 100, Rgb([40, 40, 40])));
+        assert_eq!(smart.crop_box(&flat), (100, 0, 100, 100));
+        let left = ThumbnailSettings { anchor: CropAnchor::Left, ..smart };
+        assert_eq!(left.crop_box(&flat), (0, 0, 100, 100));
+    }
+
+    #[test]
+    fn makes_small_placeholders() {
+        let thumbnail = DynamicImage::ImageRgb8(RgbImage::from_pixel(500, 375, Rgb([200, 30, 30])));
+        let placeholder = placeholder(&thumbnail).unwrap();
+        assert!(placeholder.data_uri.starts_with("data:image/jpeg;base64,"));
+        assert!(placeholder.data_uri.len() < 1024, "{}", placeholder.data_uri.len());
+        for (channel, expected) in placeholder.colour.iter().zip([200u8, 30, 30].iter()) {
+            assert!((*channel as i32 - *expected as i32).abs() <= 4, "{:?}", placeholder.colour);
+        }
+    }
+
+    #[test]
+    fn caches_thumbnail_info() {
+        let dir = PathBuf::from("./test_files_out/thumbnail_info");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        assert_eq!(ThumbnailInfoCache::load(&dir).get("a.jpg.500w.q85-triangle.jpg"), None);
+
+        let info = describe(&DynamicImage::ImageRgb8(RgbImage::from_pixel(500, 375, Rgb([200, 30, 30]))));
+        assert_eq!(info.size, (500, 375));
+        let mut cache = ThumbnailInfoCache::default();
+        cache.insert(String::from("a.jpg.500w.q85-triangle.jpg"), info.clone());
+        cache.save(&dir).unwrap();
+        assert_eq!(ThumbnailInfoCache::load(&dir).get("a.jpg.500w.q85-triangle.jpg"), Some(&info));
+
+        fs::write(dir.join(THUMBNAIL_INFO_FILE_NAME), b"not json").unwrap();
+        assert_eq!(ThumbnailInfoCache::load(&dir).get("a.jpg.500w.q85-triangle.jpg"), None);
+    }
+
+    #[test]
+    fn parses_filters() {
+        for name in FILTER_NAMES.iter() {
+            assert!(parse_filter(name).is_some(), "{}", name);
+        }
+        assert_eq!(parse_filter("Lanczos3"), Some(FilterType::Lanczos3));
+        assert_eq!(parse_filter("bicubic"), None);
+    }
+
+    #[test]
+    fn writes_progressive_jpegs() {
+        let dir = PathBuf::from("./test_files_out/thumbnail");
+        fs::create_dir_all(&dir).unwrap();
+        let out_file = dir.join("thumb.500w.jpg");
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(40, 20, Rgb([20, 120, 220])));
+        encode(&image, &out_file, &ThumbnailSettings::default()).unwrap();
+
+        let bytes = fs::read(&out_file).unwrap();
+        // a progressive jpeg has a SOF2 marker, and nothing but a jfif header before it
+        assert!(bytes.windows(2).any(|marker| marker == [0xFF, 0xC2]));
+        assert!(!bytes.windows(2).any(|marker| marker == [0xFF, 0xE1]));
+        assert_eq!(image::open(&out_file).unwrap().dimensions(), (40, 20));
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+
+use chrono::{Datelike,DateTime,Local,NaiveDate,NaiveDateTime};
+
+use super::util::{ActionRecord,PhotoAction};
+
+// where the timeline pages are written, in the cacheDir of the top directory of
+// the tree so it can't be mistaken for an album of the same name
+pub const TIMELINE_DIR_NAME: &str = "timeline";
+
+pub const MONTH_NAMES: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December",
+];
+
+// every photo in the tree by the day it was taken, each day's photos in the
+// order they were taken
+pub fn collect_days(root: &ActionRecord) -> BTreeMap<NaiveDate, Vec<&PhotoAction>> {
+    let mut dated : Vec<(NaiveDateTime, &PhotoAction)> = root.get_all_photos().into_iter()
+        .filter_map(|pa| capture_time(pa).map(|time| (time, pa)))
+        .collect();
+    // stable, so photos taken at the same moment stay in tree order
+    dated.sort_by_key(|(time, _)| *time);
+    let mut days : BTreeMap<NaiveDate, Vec<&PhotoAction>> = BTreeMap::new();
+    for (time, pa) in dated {
+        days.entry(time.date()).or_default().push(pa);
+    }
+    days
+}
+
+// when the photo was taken according to its exif, or failing that when the
+// file was last modified
+pub fn capture_time(pa: &PhotoAction) -> Option<NaiveDateTime> {
+    pa.get_metadata()
+        .and_then(|metadata| metadata.taken_at)
+        .or_else(|| {
+            let modified = pa.get_abs_actual().metadata().ok()?.modified().ok()?;
+            Some(DateTime::<Local>::from(modified).naive_local())
+        })
+}
+
+pub fn year_file_name(year: i32) -> String {
+    format!("{:04}.html", year)
+}
+
+pub fn month_file_name(year: i32, month: u32) -> String {
+    format!("{:04}-{:02}.html", year, month)
+}
+
+pub fn day_file_name(day: &NaiveDate) -> String {
+    format!("{:04}-{:02}-{:02}.html", day.year(), day.month(), day.day())
+}
+
+pub fn month_name(month: u32) -> &'static str {
+    MONTH_NAMES[(month as usize).clamp(1, 12) - 1]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::{Path,PathBuf};
+    use crate::media::MediaKind;
+    use crate::metadata::PhotoMetadata;
+    use crate::util::test::album_fixture;
+
+    fn taken(root: &Path, name: &str, at: &str) -> PhotoAction {
+        let pa = PhotoAction::new(root.to_path_buf(), PathBuf::from(name), PathBuf::from(format!("cacheDir/{}", name)), MediaKind::Image);
+        pa.set_metadata(PhotoMetadata {
+            taken_at: NaiveDateTime::parse_from_str(at, "%Y-%m-%d %H:%M").ok(),
+            ..PhotoMetadata::default()
+        });
+        pa
+    }
+
+    #[test]
+    fn groups_photos_by_day() {
+        let mut root = album_fixture();
+        let root_path = root.get_path();
+        root.add_photo_action(taken(&root_path, "evening.jpg", "2021-06-01 18:30"));
+        root.add_photo_action(taken(&root_path, "new_year.jpg", "2022-01-01 00:10"));
+        root.get_subdirs()[0].get_photos()[0].set_metadata(PhotoMetadata {
+            taken_at: NaiveDateTime::parse_from_str("2021-06-01 08:00", "%Y-%m-%d %H:%M").ok(),
+            ..PhotoMetadata::default()
+        });
+
+        let days = collect_days(&root);
+        let june = NaiveDate::from_ymd_opt(2021, 6, 1).unwrap();
+        assert_eq!(days.len(), 2);
+        let names : Vec<&str> = days[&june].iter().map(|pa| pa.get_name().unwrap()).collect();
+        assert_eq!(names, vec!["boat.jpg", "evening.jpg"]);
+        assert_eq!(day_file_name(&june), "2021-06-01.html");
+        assert_eq!(month_file_name(2021, 6), "2021-06.html");
+        assert_eq!(month_name(6), "June");
+    }
+}
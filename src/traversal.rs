@@ -0,0 +1,188 @@
+use std::{
+    collections::HashMap,
+    fs::{self,DirEntry,Metadata},
+    path::{Path,PathBuf},
+};
+
+use log::{debug,info,warn};
+
+// files and folders left behind by NAS boxes, file managers and filesystems
+pub const JUNK_NAMES: [&str; 5] = ["@eaDir", ".thumbnails", "Thumbs.db", "lost+found", ".DS_Store"];
+
+#[derive(Clone,Debug,Default)]
+pub struct TraversalPolicy {
+    pub follow_symlinks: bool,
+    pub one_file_system: bool,
+    pub include_hidden:  bool,
+    pub include_junk:    bool,
+}
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum EntryKind {
+    Dir,
+    File,
+}
+
+// applies a TraversalPolicy while walking a tree, remembering which directories
+// have been entered so that each is only walked once, however many symlinks
+// lead to it
+pub struct Traversal {
+    policy:  TraversalPolicy,
+    root_device: Option<u64>,
+    // each directory entered, by its canonical path, and the path it was walked as
+    visited: HashMap<PathBuf, PathBuf>,
+}
+
+impl Traversal {
+    pub fn new(policy: &TraversalPolicy, root: &Path) -> Self {
+        Self {
+            policy:      policy.clone(),
+            root_device: fs::metadata(root).ok().and_then(|metadata| device_id(&metadata)),
+            visited:     HashMap::new(),
+        }
+    }
+
+    // returns false if this directory has already been walked, either because
+    // a symlink points back up the tree or another symlink led to it first
+    pub fn enter_dir(&mut self, path: &Path) -> bool {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        match self.visited.get(&canonical) {
+            None => {
+                self.visited.insert(canonical, path.to_path_buf());
+                true
+            },
+            Some(first) if is_cycle(path, first) => {
+                warn!("{:?} - leads back to {:?}, skipping symlink cycle", path, first);
+                false
+            },
+            Some(first) => {
+                info!("{:?} - already included via {:?}, skipping", path, first);
+                false
+            },
+        }
+    }
+
+    // decides whether an entry should be walked into or considered as a photo
+    pub fn classify(&self, entry: &DirEntry) -> Option<EntryKind> {
+        let os_file_name = entry.file_name();
+        let file_name = os_file_name.to_string_lossy();
+        if !self.policy.include_hidden && file_name.starts_with('.') {
+            return None;
+        }
+        if !self.policy.include_junk && JUNK_NAMES.contains(&file_name.as_ref()) {
+            debug!("{:?} - ignoring junk", entry.path());
+            return None;
+        }
+
+        let file_type = entry.file_type().ok()?;
+        let metadata = if file_type.is_symlink() {
+            if !self.policy.follow_symlinks {
+                debug!("{:?} - not following symlink", entry.path());
+                return None;
+            }
+            match fs::metadata(entry.path()) {
+                Ok(metadata) => metadata,
+                Err(_) => {
+                    warn!("{:?} - broken symlink", entry.path());
+                    return None;
+                },
+            }
+        } else {
+            entry.metadata().ok()?
+        };
+
+        if metadata.is_dir() {
+            if self.policy.one_file_system && device_id(&metadata) != self.root_device {
+                debug!("{:?} - on another filesystem, skipping", entry.path());
+                return None;
+            }
+            Some(EntryKind::Dir)
+        } else if metadata.is_file() {
+            Some(EntryKind::File)
+        } else {
+            None
+        }
+    }
+}
+
+// the walk is depth first, so a directory walked as an ancestor of `path` is
+// still being walked, and entering it again would never end
+fn is_cycle(path: &Path, first: &Path) -> bool {
+    path.starts_with(first)
+}
+
+#[cfg(unix)]
+fn device_id(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device_id(_metadata: &Metadata) -> Option<u64> {
+    None
+}
+
+#[cfg(all(test,unix))]
+mod test {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    fn kinds(traversal: &Traversal, dir: &Path) -> Vec<(String, EntryKind)> {
+        let mut kinds : Vec<(String, EntryKind)> = fs::read_dir(dir).unwrap()
+            .filter_map(|entry_res| entry_res.ok())
+            .filter_map(|entry| traversal.classify(&entry)
+                .map(|kind| (entry.file_name().to_string_lossy().into_owned(), kind)))
+            .collect();
+        kinds.sort_by(|a, b| a.0.cmp(&b.0));
+        kinds
+    }
+
+    #[test]
+    fn applies_policy() {
+        let root = PathBuf::from("./test_files_out/traversal");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("album").join("@eaDir")).unwrap();
+        fs::create_dir_all(root.join(".hidden")).unwrap();
+        fs::write(root.join("album").join("a.jpg"), b"photo").unwrap();
+        fs::write(root.join("Thumbs.db"), b"junk").unwrap();
+        symlink("album", root.join("linked")).unwrap();
+        symlink("a.jpg", root.join("album").join("linked.jpg")).unwrap();
+        symlink("..", root.join("album").join("loop")).unwrap();
+        symlink("album", root.join("also_linked")).unwrap();
+
+        let strict = Traversal::new(&TraversalPolicy::default(), &root);
+        assert_eq!(kinds(&strict, &root), vec![(String::from("album"), EntryKind::Dir)]);
+        assert_eq!(kinds(&strict, &root.join("album")), vec![(String::from("a.jpg"), EntryKind::File)]);
+
+        let policy = TraversalPolicy {
+            follow_symlinks: true,
+            include_hidden:  true,
+            include_junk:    true,
+            ..TraversalPolicy::default()
+        };
+        let mut relaxed = Traversal::new(&policy, &root);
+        assert_eq!(kinds(&relaxed, &root), vec![
+            (String::from(".hidden"), EntryKind::Dir),
+            (String::from("Thumbs.db"), EntryKind::File),
+            (String::from("album"), EntryKind::Dir),
+            (String::from("also_linked"), EntryKind::Dir),
+            (String::from("linked"), EntryKind::Dir),
+        ]);
+        assert_eq!(kinds(&relaxed, &root.join("album")), vec![
+            (String::from("@eaDir"), EntryKind::Dir),
+            (String::from("a.jpg"), EntryKind::File),
+            (String::from("linked.jpg"), EntryKind::File),
+            (String::from("loop"), EntryKind::Dir),
+        ]);
+
+        // the symlinked folder and the loop lead back to folders already walked
+        assert!(relaxed.enter_dir(&root));
+        assert!(relaxed.enter_dir(&root.join("album")));
+        assert!(!relaxed.enter_dir(&root.join("linked")));
+        assert!(!relaxed.enter_dir(&root.join("album").join("loop")));
+        assert!(!relaxed.enter_dir(&root.join("also_linked")));
+        // only the loop is a cycle, the others are the same folder linked twice
+        assert!(is_cycle(&root.join("album").join("loop"), &root));
+        assert!(!is_cycle(&root.join("also_linked"), &root.join("album")));
+    }
+}
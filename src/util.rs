@@ -1,21 +1,97 @@
-use std::path::PathBuf;
+// explicit returns are this file's style
+#![allow(clippy::needless_return)]
+use std::{
+    fs,
+    path::{Path,PathBuf},
+    sync::OnceLock,
+    time::SystemTime,
+};
+use log::debug;
+use serde::{Serialize,Serializer};
+use super::formats;
+use super::media::MediaKind;
+use super::metadata::{PhotoMetadata,PhotoTags};
+use super::progress::RenderOutcome;
+use super::raw;
+use super::thumbnail::{self,Placeholder,ThumbnailInfo,ThumbnailSettings};
 
 
+// the tree as scanned, serialised for --export
+#[derive(Serialize)]
 pub struct ActionRecord {
     dir: PathBuf,
     sub_dirs: Vec<ActionRecord>,
     photos: Vec<PhotoAction>,
+    #[serde(skip)]
+    thumbnails: ThumbnailSettings,
+    // the directory's README.md, rendered to html
+    description: Option<String>,
+    // how far below the top of the tree this directory is
+    depth: usize,
+    // when files were last added, removed or renamed here by anyone but us
+    #[serde(skip)]
+    modified: Option<SystemTime>,
 }
 
 impl ActionRecord {
-    pub fn new(path: &PathBuf) -> Self {
+    pub fn new(path: &Path) -> Self {
         Self {
-            dir: path.clone(),
+            dir: path.to_path_buf(),
             sub_dirs: Vec::new(),
             photos: Vec::new(),
+            thumbnails: ThumbnailSettings::default(),
+            description: None,
+            depth: 0,
+            modified: None,
         }
     }
 
+    // how the photos directly in this directory are thumbnailed
+    pub fn set_thumbnail_settings(&mut self, thumbnails: ThumbnailSettings) {
+        self.thumbnails = thumbnails;
+    }
+
+    pub fn get_thumbnail_settings(&self) -> &ThumbnailSettings {
+        &self.thumbnails
+    }
+
+    pub fn set_description(&mut self, description: Option<String>) {
+        self.description = description;
+    }
+
+    pub fn get_description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+    }
+
+    pub fn get_depth(&self) -> usize {
+        self.depth
+    }
+
+    // read before the build writes anything into the directory, as writing
+    // the pages moves its modified time. A time no later than the pages the
+    // last build wrote is that build's own doing, and isn't kept
+    // is_none_or would need rust 1.82
+    #[allow(clippy::unnecessary_map_or)]
+    pub fn read_modified(&mut self) {
+        let modified = |path: &Path| path.metadata().and_then(|metadata| metadata.modified()).ok();
+        let last_build = fs::read_dir(&self.dir).into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(is_html_file)
+            .filter_map(|path| modified(&path))
+            .max();
+        self.modified = modified(&self.dir).filter(|dir_modified| last_build.map_or(true, |last_build| *dir_modified > last_build));
+    }
+
+    pub fn get_modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+
     pub fn get_path(&self) -> PathBuf {
         return self.dir.clone();
     }
@@ -35,18 +111,143 @@ impl ActionRecord {
     pub fn get_photos(&self) -> &[PhotoAction] {
         return &(self.photos)
     }
+
+    // this record and every record below it, parents before children
+    pub fn get_all_records(&self) -> Vec<&ActionRecord> {
+        let mut records = vec![self];
+        for sub_dir in self.sub_dirs.iter() {
+            records.extend(sub_dir.get_all_records());
+        }
+        return records;
+    }
+
+    // every photo in this record and all records below it
+    pub fn get_all_photos(&self) -> Vec<&PhotoAction> {
+        return self.get_all_records().into_iter()
+            .flat_map(|record| record.photos.iter())
+            .collect();
+    }
 }
 
-#[derive(Clone)]
+// paths are relative to `dir`, the photo's album. It is left out when
+// serialised, as whatever holds the photo knows where its album is
+#[derive(Clone,Serialize)]
 pub struct PhotoAction {
+    #[serde(skip)]
     dir:       PathBuf,
+    #[serde(rename = "file")]
     actual:    PathBuf,
+    #[serde(rename = "thumbnail")]
     downsized: PathBuf,
+    kind:      MediaKind,
+    // a raw file shot alongside this one, shown as a single album item
+    raw_companion: Option<PathBuf>,
+    // pixel size of the original, for the formats whose header can be read
+    #[serde(serialize_with = "serialize_once")]
+    dimensions: OnceLock<(u32, u32)>,
+    // pixel size of the thumbnail, known once it has been rendered
+    #[serde(serialize_with = "serialize_once")]
+    thumbnail_size: OnceLock<(u32, u32)>,
+    #[serde(skip)]
+    placeholder: OnceLock<Placeholder>,
+    // the larger copy shown on the photo's detail page, when those are built
+    web_rendition: Option<PathBuf>,
+    #[serde(serialize_with = "serialize_once")]
+    metadata: OnceLock<PhotoMetadata>,
+    // from a sidecar when scanned, or else from the photo's own metadata
+    #[serde(serialize_with = "serialize_once")]
+    caption: OnceLock<String>,
+    tags: PhotoTags,
+}
+
+// unset values are written as null
+fn serialize_once<T: Serialize, S: Serializer>(value: &OnceLock<T>, serializer: S) -> Result<S::Ok, S::Error> {
+    value.get().serialize(serializer)
 }
 
 impl PhotoAction {
-    pub fn new(dir: PathBuf, actual: PathBuf, downsized: PathBuf) -> Self {
-        PhotoAction {dir, actual, downsized}
+    pub fn new(dir: PathBuf, actual: PathBuf, downsized: PathBuf, kind: MediaKind) -> Self {
+        PhotoAction {
+            dir, actual, downsized, kind,
+            raw_companion: None,
+            dimensions: OnceLock::new(),
+            thumbnail_size: OnceLock::new(),
+            placeholder: OnceLock::new(),
+            web_rendition: None,
+            metadata: OnceLock::new(),
+            caption: OnceLock::new(),
+            tags: PhotoTags::default(),
+        }
+    }
+
+    pub fn set_raw_companion(&mut self, raw_companion: PathBuf) {
+        self.raw_companion = Some(raw_companion);
+    }
+
+    pub fn get_raw_companion(&self) -> Option<&PathBuf> {
+        self.raw_companion.as_ref()
+    }
+
+    pub fn set_dimensions(&self, width: u32, height: u32) {
+        let _ = self.dimensions.set((width, height));
+    }
+
+    pub fn get_dimensions(&self) -> Option<(u32, u32)> {
+        self.dimensions.get().copied()
+    }
+
+    // called from the render phase, which only has shared access
+    pub fn set_thumbnail_size(&self, width: u32, height: u32) {
+        let _ = self.thumbnail_size.set((width, height));
+    }
+
+    pub fn get_thumbnail_size(&self) -> Option<(u32, u32)> {
+        self.thumbnail_size.get().copied()
+    }
+
+    pub fn set_placeholder(&self, placeholder: Placeholder) {
+        let _ = self.placeholder.set(placeholder);
+    }
+
+    pub fn get_placeholder(&self) -> Option<&Placeholder> {
+        self.placeholder.get()
+    }
+
+    pub fn set_web_rendition(&mut self, web_rendition: PathBuf) {
+        self.web_rendition = Some(web_rendition);
+    }
+
+    pub fn get_web_rendition(&self) -> Option<&PathBuf> {
+        self.web_rendition.as_ref()
+    }
+
+    pub fn set_metadata(&self, metadata: PhotoMetadata) {
+        let _ = self.metadata.set(metadata);
+    }
+
+    pub fn get_metadata(&self) -> Option<&PhotoMetadata> {
+        self.metadata.get()
+    }
+
+    // the first caption found is kept
+    pub fn set_caption(&self, caption: String) {
+        let _ = self.caption.set(caption);
+    }
+
+    pub fn get_caption(&self) -> Option<&str> {
+        self.caption.get().map(String::as_str)
+    }
+
+    pub fn set_tags(&mut self, tags: PhotoTags) {
+        self.tags = tags;
+    }
+
+    pub fn get_tags(&self) -> &PhotoTags {
+        &self.tags
+    }
+
+    pub fn get_kind(&self) -> MediaKind {
+        self.kind
     }
 
     pub fn get_actual(&self) -> PathBuf {
@@ -61,6 +262,14 @@ impl PhotoAction {
         return self.dir.clone();
     }
 
+    pub fn get_abs_actual(&self) -> PathBuf {
+        self.dir.join(&self.actual)
+    }
+
+    pub fn get_abs_downsized(&self) -> PathBuf {
+        self.dir.join(&self.downsized)
+    }
+
     pub fn get_name(&self) -> Option<&str> {
         if let Some(os_str) = self.actual.file_name() {
             return Some(os_str.to_str().unwrap());
@@ -71,13 +280,10 @@ impl PhotoAction {
     }
 }
 
-use image::{
-    GenericImageView,
-    imageops::FilterType,
-};
-
 // file system manipulation
-pub fn downsize_image(in_file: &PathBuf, out_file: &PathBuf, width: u32, force_regen:bool) {
+// renders a thumbnail, describing it when it was rendered. Up to date
+// thumbnails are left alone and not described
+pub fn downsize_image(in_file: &PathBuf, out_file: &PathBuf, settings: &ThumbnailSettings, force_regen:bool) -> (RenderOutcome, Option<ThumbnailInfo>) {
     if !force_regen && in_file.exists() && out_file.exists() {
         // println!("\tBoth exist");
         let in_file_creation_time = in_file.metadata().unwrap().created().unwrap();
@@ -86,24 +292,97 @@ pub fn downsize_image(in_file: &PathBuf, out_file: &PathBuf, width: u32, force_r
             // the file to be converted already seems to have been converted
             // the downsized file is newer than the original, so lets not
             // waste time
-            println!("> {:?} - up to date", in_file.file_name().unwrap());
-            return;
+            return (RenderOutcome::UpToDate, None);
         }
     }
-    if let Ok(img) = image::open(in_file) {
-        println!("> {:?} - rendering", in_file.file_name().unwrap());
-        let (img_height,img_width) = img.dimensions();
-        let aspect_ratio : f32 = (img_height as f32)/(img_width as f32);
-        let new_height: u32 = (aspect_ratio*(width as f32)) as u32;
-        let resized_image = img.resize(width, new_height, FilterType::Triangle);
-        resized_image.save(out_file).unwrap();
+    if let Some(outcome) = downsize_animated_gif(in_file, out_file, settings) {
+        return outcome;
+    }
+    // raw files are thumbnailed from the jpeg preview the camera embedded
+    let opened = if raw::is_raw_file(in_file) {
+        raw::open_preview(in_file)
+    } else if settings.fast_jpeg {
+        formats::open_image_scaled(in_file, |width, height| settings.scaled_size(width, height))
     } else {
-        println!("> {:?} - could not open", in_file.file_name().unwrap());
+        formats::open_image(in_file)
+    };
+    match opened {
+        Ok(img) => {
+            let resized_image = thumbnail::render(&img, settings);
+            match thumbnail::encode(&resized_image, out_file, settings) {
+                Ok(_) => (RenderOutcome::Rendered, Some(thumbnail::describe(&resized_image))),
+                Err(reason) => {
+                    debug!("{:?} - {}", out_file, reason);
+                    (RenderOutcome::Failed, None)
+                },
+            }
+        },
+        Err(reason) => {
+            debug!("{:?} - {}", in_file, reason);
+            (RenderOutcome::Failed, None)
+        },
+    }
+}
+
+// animated gifs are resized frame by frame when their thumbnail is a gif, so it
+// stays animated. Returns None for anything else, which is left to the still
+// image path
+#[cfg(feature = "gif")]
+fn downsize_animated_gif(in_file: &Path, out_file: &Path, settings: &ThumbnailSettings) -> Option<(RenderOutcome, Option<ThumbnailInfo>)> {
+    use std::{fs::File,io::BufReader};
+    use image::{
+        AnimationDecoder,
+        DynamicImage,
+        Frame,
+        codecs::gif::{GifDecoder,GifEncoder,Repeat},
+        imageops,
+    };
+    let gif_out = out_file.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("gif"));
+    if !gif_out || formats::detect_format(in_file) != Some(formats::PhotoFormat::Gif) {
+        return None;
+    }
+    let decoder = GifDecoder::new(BufReader::new(File::open(in_file).ok()?)).ok()?;
+    let frames = decoder.into_frames().collect_frames().ok()?;
+    if frames.len() < 2 {
+        return None;
+    }
+    let (frame_width, frame_height) = frames[0].buffer().dimensions();
+    let (width, height) = settings.scaled_size(frame_width, frame_height);
+    // every frame is cropped the same way as the first
+    let first_frame = DynamicImage::ImageRgba8(imageops::resize(frames[0].buffer(), width, height, settings.filter));
+    let (x, y, crop_width, crop_height) = settings.crop_box(&first_frame);
+    let info = thumbnail::describe(&first_frame.crop_imm(x, y, crop_width, crop_height));
+    let resized_frames = frames.into_iter().map(|frame| {
+        let delay = frame.delay();
+        let buffer = imageops::resize(frame.buffer(), width, height, settings.filter);
+        let buffer = imageops::crop_imm(&buffer, x, y, crop_width, crop_height).to_image();
+        Frame::from_parts(buffer, 0, 0, delay)
+    });
 
+    let mut encoder = GifEncoder::new(File::create(out_file).ok()?);
+    let result = encoder.set_repeat(Repeat::Infinite)
+        .and_then(|_| encoder.encode_frames(resized_frames));
+    match result {
+        Ok(_) => Some((RenderOutcome::Rendered, Some(info))),
+        Err(reason) => {
+            debug!("{:?} - {}", in_file, reason);
+            Some((RenderOutcome::Failed, None))
+        },
     }
 }
 
-pub fn get_cache_dir_path<'a>(original_path: &'a PathBuf, cache_dir_name: &str) -> PathBuf {
+#[cfg(not(feature = "gif"))]
+fn downsize_animated_gif(_in_file: &Path, _out_file: &Path, _settings: &ThumbnailSettings) -> Option<(RenderOutcome, Option<ThumbnailInfo>)> {
+    None
+}
+
+// `path` relative to `root`, with forward slashes on every platform, as
+// written into pages, indexes and manifests. "" for the root itself
+pub fn relative(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root).unwrap_or(path).to_string_lossy().replace('\\', "/")
+}
+
+pub fn get_cache_dir_path(original_path: &Path, cache_dir_name: &str) -> PathBuf {
     let file_name = original_path.file_name().unwrap();
     let file_path = original_path.parent().unwrap();
     let new_path = file_path.join(cache_dir_name).join(file_name);
@@ -113,30 +392,135 @@ pub fn get_cache_dir_path<'a>(original_path: &'a PathBuf, cache_dir_name: &str)
     return new_path;
 }
 
-pub fn is_image_file(original_path: &PathBuf) -> bool {
+pub fn is_html_file(original_path: &PathBuf) -> bool {
     if let Some(file_extension) = original_path.extension() {
         let extension_str = file_extension.to_str().unwrap();
-        for cand in ["jpg","jpeg","png"].iter() {
-            if *cand == extension_str {
-                return true;
-            }
+        if extension_str.eq_ignore_ascii_case("html") {
+            return true;
         }
         return false;
     } else {
-        println!("could not get extension on {:?}",original_path);
+        debug!("could not get extension on {:?}",original_path);
         return false;
     }
 }
 
-pub fn is_html_file(original_path: &PathBuf) -> bool {
-    if let Some(file_extension) = original_path.extension() {
-        let extension_str = file_extension.to_str().unwrap();
-        if extension_str == "html" {
-            return true;
-        }
-        return false;
+pub fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len()-1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
     } else {
-        println!("could not get extension on {:?}",original_path);
-        return false;
+        format!("{:.1} {}", value, units[unit])
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use image::GenericImageView;
+    use crate::metadata::PhotoTags;
+
+    // the tree the index, export and page tests share: /album with a `trip`
+    // album holding a captioned, titled and tagged boat.jpg
+    pub fn album_fixture() -> ActionRecord {
+        let root_path = PathBuf::from("/album");
+        let sub_path = root_path.join("trip");
+        let mut root = ActionRecord::new(&root_path);
+        let mut sub = ActionRecord::new(&sub_path);
+        sub.set_depth(1);
+        let mut pa = PhotoAction::new(sub_path, PathBuf::from("boat.jpg"), PathBuf::from("cacheDir/boat.jpg.500w.jpg"), MediaKind::Image);
+        pa.set_tags(PhotoTags {
+            title:    Some(String::from("Harbour")),
+            keywords: vec![String::from("sea")],
+            ..PhotoTags::default()
+        });
+        pa.set_caption(String::from("at dusk"));
+        sub.add_photo_action(pa);
+        root.add_subdir_action(sub);
+        root
+    }
+
+    #[test]
+    fn formats_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3*1024*1024*1024), "3.0 GiB");
+    }
+
+    #[test]
+    fn fast_jpeg_path_matches_full_decode() {
+        use std::fs;
+        use image::{Rgb,RgbImage};
+
+        let dir = PathBuf::from("./test_files_out/fast_jpeg");
+        fs::create_dir_all(&dir).unwrap();
+        let in_file = dir.join("large.jpg");
+        RgbImage::from_fn(2400, 1600, |x, y| Rgb([(x % 256) as u8, (y % 256) as u8, 90])).save(&in_file).unwrap();
+
+        let fast = ThumbnailSettings { size: 300, ..ThumbnailSettings::default() };
+        let full = ThumbnailSettings { fast_jpeg: false, ..fast };
+        for (settings, name) in [(fast, "large.fast.jpg"), (full, "large.full.jpg")].iter() {
+            let out_file = dir.join(name);
+            assert_eq!(downsize_image(&in_file, &out_file, settings, true).0, RenderOutcome::Rendered);
+            assert_eq!(image::open(&out_file).unwrap().dimensions(), (300, 200));
+        }
     }
-}
\ No newline at end of file
+
+    // compares the reduced scale jpeg decode against decoding every pixel, on a
+    // photo the size of a 24 megapixel camera's output. Run with
+    // cargo test --release -- --ignored --nocapture benchmark
+    #[test]
+    #[ignore]
+    fn benchmark_jpeg_decode_paths() {
+        use std::{fs,time::Instant};
+        use image::{Rgb,RgbImage};
+
+        let dir = PathBuf::from("./test_files_out/benchmark");
+        fs::create_dir_all(&dir).unwrap();
+        let in_file = dir.join("camera.jpg");
+        if !in_file.exists() {
+            RgbImage::from_fn(6000, 4000, |x, y| Rgb([(x*y % 251) as u8, (x % 256) as u8, (y % 256) as u8])).save(&in_file).unwrap();
+        }
+
+        let fast = ThumbnailSettings::default();
+        let full = ThumbnailSettings { fast_jpeg: false, ..fast };
+        for (settings, name) in [(full, "full"), (fast, "fast")].iter() {
+            let out_file = dir.join(format!("camera.{}.jpg", name));
+            let start = Instant::now();
+            for _ in 0..3 {
+                assert_eq!(downsize_image(&in_file, &out_file, settings, true).0, RenderOutcome::Rendered);
+            }
+            println!("{} decode: {:.0} ms per photo", name, start.elapsed().as_secs_f64()*1000.0/3.0);
+        }
+    }
+
+    #[cfg(feature = "gif")]
+    #[test]
+    fn animated_gifs_stay_animated() {
+        use std::fs::{self,File};
+        use image::{AnimationDecoder,Delay,Frame,Rgba,RgbaImage,codecs::gif::{GifDecoder,GifEncoder}};
+
+        let dir = PathBuf::from("./test_files_out/gif");
+        fs::create_dir_all(&dir).unwrap();
+        let in_file = dir.join("anim.gif");
+        let out_file = dir.join("anim.gif.16w.gif");
+        {
+            let mut encoder = GifEncoder::new(File::create(&in_file).unwrap());
+            let frames = [Rgba([255, 0, 0, 255]), Rgba([0, 0, 255, 255])].iter()
+                .map(|colour| Frame::from_parts(RgbaImage::from_pixel(64, 32, *colour), 0, 0, Delay::from_numer_denom_ms(100, 1)));
+            encoder.encode_frames(frames).unwrap();
+        }
+
+        assert_eq!(downsize_image(&in_file, &out_file, &ThumbnailSettings { size: 16, ..ThumbnailSettings::default() }, true).0, RenderOutcome::Rendered);
+        let frames = GifDecoder::new(File::open(&out_file).unwrap()).unwrap()
+            .into_frames().collect_frames().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].buffer().dimensions(), (16, 8));
+    }
+}